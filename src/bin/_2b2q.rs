@@ -1,15 +1,26 @@
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
-    path::PathBuf,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use _2b2q::{
-    nn::{log, make_expected_result, make_inputs},
-    LoggingDataPoint,
+    boosted::GrowOptions,
+    checkpoint,
+    dataset::{self, Features},
+    features::{self, FeatureExtractor},
+    model::{self, TrainingMeta},
+    network::{Activation, Halt, Optimizer, TrainOptions, Trainer},
+    nn::{error_minutes, log, residual_minutes},
+    ridge::Ridge,
+    schedule::{Schedule, Scheduler},
+    sweep::{self, SweepOptions},
+    CsvOptions, Error, Estimator, EtaModel, Intervals, LoadOptions, LoggingDataPoint, Model,
+    ModelType, OldEta, Predictor, QueueDataPoint, QueueRun, QueueType, RunFilter, Split, Target,
+    TimeFormat, TrainingDataPoint, WalkOptions,
 };
 use clap::{ArgGroup, Args, Parser, Subcommand};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 #[derive(Parser)]
 struct Cli {
@@ -22,6 +33,12 @@ enum Commands {
     New(New),
     Stat(Stat),
     Train(Train),
+    Sweep(Sweep),
+    Predict(Predict),
+    Check(Check),
+    Import(Import),
+    Dataset(Dataset),
+    Checkpoints(Checkpoints),
 }
 #[derive(Args)]
 #[clap(group = ArgGroup::new("file_path").required(true).multiple(false))]
@@ -40,6 +57,126 @@ struct New {
     /// layers which the neural net should have, for example: 10-6-2-4-1
     #[clap(value_delimiter('-'))]
     layers: Vec<u32>,
+    /// features the model encodes its inputs with, the first layer has to
+    /// match their amount of inputs
+    #[clap(long, default_value = "v1", value_parser = features::parse)]
+    features: Arc<dyn FeatureExtractor>,
+    /// encoding of the remaining time in the outputs of the model: sigmoid,
+    /// log-seconds, seconds-per-slot or hours
+    #[clap(long, default_value = "sigmoid")]
+    target: Target,
+    /// activation of the hidden layers: sigmoid, tanh or relu, the output
+    /// layer always uses a sigmoid
+    #[clap(long, default_value = "sigmoid")]
+    activation: Activation,
+}
+#[derive(Args)]
+struct FormatOptions {
+    /// format of timestamps in the data: auto, ms, s, iso or a chrono format
+    /// string such as `%d.%m.%Y %H:%M:%S`
+    #[clap(long, default_value = "auto")]
+    time_format: TimeFormat,
+    /// delimiter of csv files, detected from the header if not specified,
+    /// use `tab` for tabs
+    #[clap(long, value_parser = parse_char)]
+    delimiter: Option<char>,
+    /// character used to quote csv fields
+    #[clap(long, default_value = "\"", value_parser = parse_char)]
+    quote: char,
+    /// lines of csv files starting with this character are ignored
+    #[clap(long, default_value = "#", value_parser = parse_char)]
+    comment: char,
+}
+impl FormatOptions {
+    fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            time_format: self.time_format.clone(),
+            csv: CsvOptions {
+                delimiter: self.delimiter,
+                quote: Some(self.quote),
+                comment: Some(self.comment),
+                ..CsvOptions::default()
+            },
+        }
+    }
+}
+#[derive(Args)]
+struct DirOptions {
+    /// additional directory from which to read data, may be repeated
+    #[clap(long = "data-dir", value_name = "DIR")]
+    extra_dirs: Vec<PathBuf>,
+    /// also read files in subdirectories
+    #[clap(short, long)]
+    recursive: bool,
+    /// only read files whose path relative to the data directory matches the
+    /// glob, for example `2024-*/**.csv`, may be repeated
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// never read files whose path relative to the data directory matches the
+    /// glob, for example `**/broken/**`, may be repeated
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+}
+impl DirOptions {
+    fn roots(&self, data_dir: &Path) -> Vec<PathBuf> {
+        std::iter::once(data_dir.to_owned())
+            .chain(self.extra_dirs.iter().cloned())
+            .collect()
+    }
+    fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            recursive: self.recursive,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+        }
+    }
+}
+#[derive(Args)]
+struct DataOptions {
+    #[clap(flatten)]
+    dirs: DirOptions,
+    /// read the runs from a cache written by `dataset build`, the cache is
+    /// rebuilt if it is missing or the data files changed
+    #[clap(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+    /// skip runs with less than the specified amount of data points
+    #[clap(long, default_value_t = 2)]
+    min_points: usize,
+    #[clap(flatten)]
+    format: FormatOptions,
+    /// only use runs of the specified queue type (normal or priority), runs
+    /// without a queue type are considered normal
+    #[clap(long)]
+    queue: Option<QueueType>,
+    /// only use runs recorded on the specified server
+    #[clap(long)]
+    server: Option<String>,
+    /// only use runs recorded with the specified client version
+    #[clap(long)]
+    client_version: Option<String>,
+    /// refuse models which don't use the specified features, every model
+    /// uses the features it was created with
+    #[clap(long, value_parser = features::parse)]
+    features: Option<Arc<dyn FeatureExtractor>>,
+}
+impl DataOptions {
+    fn check_features(&self, path: &Path, features: &dyn FeatureExtractor) {
+        match &self.features {
+            Some(x) if x.name() != features.name() => exit_with(format!(
+                "{path:?} uses features {}, not {}",
+                features.name(),
+                x.name()
+            )),
+            _ => {}
+        }
+    }
+    fn filter(&self) -> RunFilter {
+        RunFilter {
+            queue: self.queue,
+            server: self.server.clone(),
+            client_version: self.client_version.clone(),
+        }
+    }
 }
 #[derive(Args)]
 /// prints the current estimation of the specified models neatly organized
@@ -49,12 +186,14 @@ struct Stat {
     data_dir: PathBuf,
     /// models which to include in comparison
     models: Vec<PathBuf>,
+    #[clap(flatten)]
+    data: DataOptions,
 }
 #[derive(Args)]
 /// trains the specified neural network on the data
-/// 
-/// WARNING: changes apply immediately, make a backup if you are worried
-/// about it messing up
+///
+/// the model file is only replaced when an iteration lowers the error on the
+/// validation runs, or on the training runs if there are none
 #[clap(group = ArgGroup::new("halt_condition").required(false).multiple(false))]
 struct Train {
     /// directory from which to read training data
@@ -79,12 +218,278 @@ struct Train {
     /// train until specified error rate is achieved
     #[clap(short, long, conflicts_with_all = &["epochs", "timer"], group = "halt_condition")]
     mse: Option<f64>,
-    /// momentum used by RustNN (don't change without reason)
+    /// optimiser of networks: adam or sgd
+    #[clap(long, default_value = "adam", value_parser = ["adam", "sgd"])]
+    optimizer: String,
+    /// momentum of the sgd optimiser
     #[clap(long, default_value_t = 0.1)]
     momentum: f64,
-    /// rate used for backpropagation by RustNN (don't change without reason)
-    #[clap(long, default_value_t = 0.3)]
-    rate: f64,
+    /// learning rate of networks, 0.001 for adam and 0.3 for sgd by default,
+    /// and factor the trees of boosted models are scaled by, 0.3 by default
+    #[clap(long)]
+    rate: Option<f64>,
+    /// change the rate across iterations: `step:N:FACTOR` multiplies it by
+    /// FACTOR every N iterations, `cosine:N[:MIN]` anneals it to MIN times
+    /// the rate over N iterations and restarts, `plateau:N:FACTOR` multiplies
+    /// it by FACTOR after N iterations without improvement
+    #[clap(long, default_value = "constant")]
+    schedule: Schedule,
+    /// examples per update of networks
+    #[clap(long, default_value_t = 64)]
+    batch_size: usize,
+    /// decay of the weights of networks per update, relative to the rate
+    #[clap(long, default_value_t = 0.0)]
+    weight_decay: f64,
+    /// train a model of another type with the features and target of the
+    /// model: ridge or boosted, it only replaces the model once its error is
+    /// lower, networks are created with `new`
+    #[clap(long)]
+    model_type: Option<ModelType>,
+    /// penalty on the squared weights of ridge models
+    #[clap(long, default_value_t = 1e-4)]
+    lambda: f64,
+    /// trees added to boosted models per iteration
+    #[clap(long, default_value_t = 20)]
+    trees: usize,
+    /// depth of the trees of boosted models
+    #[clap(long, default_value_t = 4)]
+    depth: usize,
+    /// hold out runs from training to report the error of the model on them:
+    /// `ratio:0.2` holds out the latest fifth of the runs, `hash:0.2[:SEED]`
+    /// a fifth picked by the hash of the file name and `date:TIME` the runs
+    /// started at or after TIME
+    #[clap(long)]
+    split: Option<Split>,
+    /// directory from which to read additional held-out runs
+    #[clap(long, value_name = "DIR")]
+    validation_dir: Option<PathBuf>,
+    /// stop after the specified amount of iterations without improving the
+    /// error
+    #[clap(long)]
+    patience: Option<u32>,
+    /// also keep a timestamped copy of the model and its training parameters
+    /// whenever it is saved, see `checkpoints`
+    #[clap(long)]
+    checkpoints: bool,
+    #[clap(flatten)]
+    data: DataOptions,
+}
+
+#[derive(Args)]
+/// trains new networks for every combination of the specified layers and
+/// training parameters, or a random sample of them, in parallel on the same
+/// runs and prints a table of them ranked by their error
+#[clap(group = ArgGroup::new("halt_condition").required(false).multiple(false))]
+struct Sweep {
+    /// directory from which to read training data
+    data_dir: PathBuf,
+    /// layers of the networks to try, for example: 10-6-1,10-8-2-4-1, the
+    /// first layer has to match the inputs of `--features`, v1 by default
+    #[clap(long, required = true, value_delimiter = ',', value_parser = parse_layers)]
+    layers: Vec<Vec<u32>>,
+    /// optimisers to try: adam, sgd
+    #[clap(long, default_value = "adam", value_delimiter = ',', value_parser = ["adam", "sgd"])]
+    optimizer: Vec<String>,
+    /// momenta to try with sgd
+    #[clap(long, default_value = "0.1", value_delimiter = ',')]
+    momentum: Vec<f64>,
+    /// rates to try, the default rate of the optimiser if not specified
+    #[clap(long, value_delimiter = ',')]
+    rate: Vec<f64>,
+    /// only train a random sample of the specified amount of combinations
+    #[clap(long)]
+    samples: Option<usize>,
+    /// seed picking the sample, the same seed picks the same combinations
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// iterations every network is trained for
+    #[clap(long, default_value_t = 10)]
+    iterations: u32,
+    /// train for specified amount of seconds per iteration
+    #[clap(short, long, group = "halt_condition")]
+    timer: Option<u64>,
+    /// train specified amount of epochs per iteration, 10 by default
+    #[clap(short, long, group = "halt_condition")]
+    epochs: Option<u32>,
+    /// change the rate across iterations like `train --schedule`
+    #[clap(long, default_value = "constant")]
+    schedule: Schedule,
+    /// examples per update
+    #[clap(long, default_value_t = 64)]
+    batch_size: usize,
+    /// decay of the weights per update, relative to the rate
+    #[clap(long, default_value_t = 0.0)]
+    weight_decay: f64,
+    /// activation of the hidden layers: sigmoid, tanh or relu
+    #[clap(long, default_value = "sigmoid")]
+    activation: Activation,
+    /// encoding of the remaining time in the outputs of the networks:
+    /// sigmoid, log-seconds, seconds-per-slot or hours
+    #[clap(long, default_value = "sigmoid")]
+    target: Target,
+    /// hold out runs from training to rank the networks by their error on
+    /// them, like `train --split`
+    #[clap(long)]
+    split: Option<Split>,
+    /// directory from which to read additional held-out runs
+    #[clap(long, value_name = "DIR")]
+    validation_dir: Option<PathBuf>,
+    /// directory in which to save the best model of every combination
+    #[clap(long)]
+    dir: Option<PathBuf>,
+    /// also write the table to the specified file
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    #[clap(flatten)]
+    data: DataOptions,
+}
+
+#[derive(Args)]
+/// predicts the remaining queue time for a single queue snapshot using the
+/// specified models
+struct Predict {
+    /// time at which the queue was joined in unix milliseconds, unix seconds
+    /// or iso 8601
+    #[clap(long, value_parser = parse_time)]
+    start_time: u64,
+    /// queue position at which the queue was joined
+    #[clap(long)]
+    start_position: u16,
+    /// queue length at which the queue was joined, defaults to `--length`
+    #[clap(long)]
+    start_length: Option<u16>,
+    /// current time in unix milliseconds, unix seconds or iso 8601, defaults
+    /// to now
+    #[clap(long, value_parser = parse_time)]
+    time: Option<u64>,
+    /// current queue position
+    #[clap(short, long)]
+    position: u16,
+    /// current queue length
+    #[clap(short, long)]
+    length: u16,
+    /// time of an earlier snapshot in unix milliseconds, unix seconds or iso
+    /// 8601, used by features such as `v2` to measure the recent rate of the
+    /// queue instead of the rate since joining
+    #[clap(long, value_parser = parse_time, requires = "recent-position")]
+    recent_time: Option<u64>,
+    /// queue position of the earlier snapshot
+    #[clap(long, requires = "recent-time")]
+    recent_position: Option<u16>,
+    /// models which to include in prediction
+    models: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+/// checks the data files in the specified directory for problems such as
+/// unparsable rows, unknown columns or inconsistent queue positions
+struct Check {
+    /// directory from which to read data
+    data_dir: PathBuf,
+    /// report gaps between two rows longer than the specified amount of minutes
+    #[clap(short, long, default_value_t = 10.0)]
+    max_gap: f64,
+    #[clap(flatten)]
+    dirs: DirOptions,
+    #[clap(flatten)]
+    format: FormatOptions,
+}
+
+#[derive(Args)]
+/// converts raw proxy logs, such as the ones written by 2bored2wait, into
+/// csv files with one file per queue run
+struct Import {
+    /// directory in which to place the csv files
+    out_dir: PathBuf,
+    /// log files from which to read `Position in queue` lines
+    logs: Vec<PathBuf>,
+    /// start a new run after the specified amount of minutes without a
+    /// position update
+    #[clap(short, long, default_value_t = 10.0)]
+    max_gap: f64,
+}
+
+fn parse_time(s: &str) -> Result<u64, String> {
+    TimeFormat::Auto
+        .parse(s)
+        .ok_or_else(|| format!("invalid time {s:?}"))
+}
+
+fn parse_char(s: &str) -> Result<char, String> {
+    match s {
+        "tab" | "\\t" => Ok('\t'),
+        s if s.chars().count() == 1 => Ok(s.chars().next().unwrap()),
+        s => Err(format!("expected a single character, found {s:?}")),
+    }
+}
+/// parses layers such as `10-6-2-4-1`
+fn parse_layers(s: &str) -> Result<Vec<u32>, String> {
+    s.split('-')
+        .map(|x| x.parse().map_err(|_| format!("invalid layers {s:?}")))
+        .collect()
+}
+
+#[derive(Args)]
+/// manages binary caches of parsed data files
+struct Dataset {
+    #[clap(subcommand)]
+    sub: DatasetCommands,
+}
+#[derive(Subcommand)]
+enum DatasetCommands {
+    Build(Build),
+}
+#[derive(Args)]
+/// parses the data files and stores the runs in a binary cache, which `stat`
+/// and `train` read with `--cache`
+struct Build {
+    /// directory from which to read data
+    data_dir: PathBuf,
+    /// path at which to place the cache
+    cache: PathBuf,
+    /// also store the network inputs and expected outputs of every data point,
+    /// computed with the specified features or `v1`
+    #[clap(
+        long,
+        value_name = "FEATURES",
+        value_parser = features::parse,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "v1"
+    )]
+    features: Option<Arc<dyn FeatureExtractor>>,
+    #[clap(flatten)]
+    dirs: DirOptions,
+    #[clap(flatten)]
+    format: FormatOptions,
+}
+
+#[derive(Args)]
+/// lists or restores the checkpoints which `train --checkpoints` keeps next to
+/// a model
+struct Checkpoints {
+    #[clap(subcommand)]
+    sub: CheckpointCommands,
+}
+#[derive(Subcommand)]
+enum CheckpointCommands {
+    List(ListCheckpoints),
+    Restore(RestoreCheckpoint),
+}
+#[derive(Args)]
+/// lists the checkpoints of a model, oldest first
+struct ListCheckpoints {
+    /// model whose checkpoints to list
+    model: PathBuf,
+}
+#[derive(Args)]
+/// replaces a model with one of its checkpoints
+struct RestoreCheckpoint {
+    /// model to replace
+    model: PathBuf,
+    /// `latest`, `best` or the start of the utc time of the checkpoint, for
+    /// example `2026-10-13`, the latest matching checkpoint is restored
+    checkpoint: String,
 }
 
 fn main() {
@@ -94,6 +499,17 @@ fn main() {
         Commands::New(opts) => new(opts),
         Commands::Stat(opts) => stat(opts),
         Commands::Train(opts) => train(opts),
+        Commands::Sweep(opts) => sweep(opts),
+        Commands::Predict(opts) => predict(opts),
+        Commands::Check(opts) => check(opts),
+        Commands::Import(opts) => import(opts),
+        Commands::Dataset(opts) => match opts.sub {
+            DatasetCommands::Build(opts) => dataset_build(opts),
+        },
+        Commands::Checkpoints(opts) => match opts.sub {
+            CheckpointCommands::List(opts) => checkpoints_list(opts),
+            CheckpointCommands::Restore(opts) => checkpoints_restore(opts),
+        },
     }
 }
 
@@ -102,21 +518,13 @@ fn new(opts: New) {
         eprintln!("exactly one of --path or --dir must be specified");
         std::process::exit(1);
     }
-
-    let model = ::nn::NN::new(&opts.layers).to_json();
-
-    fn layers_to_string(layers: &[u32]) -> String {
-        use std::fmt::Write;
-        let mut s = String::new();
-        let mut layers = layers.iter();
-        write!(&mut s, "{}", layers.next().unwrap()).ok();
-        layers.for_each(|x| {
-            write!(&mut s, "-{x}").ok();
-        });
-        s
+    if let Err(message) = model::check_layers(&*opts.features, opts.target, &opts.layers) {
+        exit_with(format!("refusing to create model: {message}"));
     }
 
-    let layers_string = layers_to_string(&opts.layers);
+    let model = Model::new(&opts.layers, opts.activation, &*opts.features, opts.target);
+
+    let layers_string = model::layers_to_string(&opts.layers);
 
     let path = opts.path.unwrap_or_else(|| {
         let mut p = opts.dir.unwrap().join(&layers_string);
@@ -138,11 +546,7 @@ fn new(opts: New) {
         }
     }
 
-    let mut file = std::fs::File::create(&path)
-        .expect("something has gone wrong creating the file for your model");
-
-    file.write_all(model.as_bytes())
-        .expect("something has gone wrong writing the model to the file");
+    model.save(&path).unwrap_or_else(|e| exit_with(e));
 
     let mut layers = opts.layers.iter();
 
@@ -154,22 +558,116 @@ fn new(opts: New) {
     layers.for_each(|l| print!("-{l}"));
     println!()
 }
+fn exit_with(err: impl Display) -> ! {
+    eprintln!("{err}");
+    std::process::exit(1)
+}
+/// parses the data files or reads them from `cache` if it is fresh, a stale
+/// or missing cache is rebuilt with the features it had
+fn load_dataset(
+    roots: &[PathBuf],
+    walk: &WalkOptions,
+    options: &LoadOptions,
+    cache: Option<&Path>,
+) -> dataset::Dataset {
+    let mut features = None;
+    if let Some(cache) = cache {
+        match dataset::Dataset::read(cache) {
+            Ok(dataset) => {
+                if dataset
+                    .is_fresh(roots, walk, options, cache)
+                    .unwrap_or_else(|e| exit_with(e))
+                {
+                    return dataset;
+                }
+                eprintln!("cache {cache:?} is stale, rebuilding it");
+                features = dataset.extractor();
+            }
+            Err(e) => eprintln!("rebuilding cache {e}"),
+        }
+    }
+    let dataset = dataset::Dataset::build(roots, walk, options, features.as_deref(), cache)
+        .unwrap_or_else(|e| exit_with(e));
+    if let Some(cache) = cache {
+        dataset.write(cache).unwrap_or_else(|e| exit_with(e));
+    }
+    dataset
+}
+/// runs together with the file they were read from and their cached feature
+/// vectors
+type LoadedRuns = Vec<(QueueRun, PathBuf, Option<Features>)>;
+/// loads the runs together with their cached feature vectors if they were
+/// computed with `features`
+fn load_runs(
+    data_dir: &Path,
+    opts: &DataOptions,
+    features: Option<&dyn FeatureExtractor>,
+) -> LoadedRuns {
+    let roots = opts.dirs.roots(data_dir);
+    load_runs_from(&roots, opts, opts.cache.as_deref(), features)
+}
+fn load_runs_from(
+    roots: &[PathBuf],
+    opts: &DataOptions,
+    cache: Option<&Path>,
+    features: Option<&dyn FeatureExtractor>,
+) -> LoadedRuns {
+    let filter = opts.filter();
+    let options = opts.format.load_options();
+    let dataset = load_dataset(roots, &opts.dirs.walk_options(), &options, cache);
+    let use_features = match (dataset.extractor(), features) {
+        (Some(a), Some(b)) => a.name() == b.name(),
+        _ => false,
+    };
+    dataset
+        .into_sources()
+        .into_iter()
+        .filter_map(|source| {
+            let run = match source.run {
+                Ok(run) if run.point_count() >= opts.min_points => run,
+                Ok(run) => {
+                    let e = Error::RunTooShort {
+                        points: run.point_count(),
+                        min_points: opts.min_points,
+                    };
+                    eprintln!("skipping {}", e.in_file(source.path));
+                    return None;
+                }
+                Err(message) => {
+                    eprintln!("skipping {:?}: {message}", source.path);
+                    return None;
+                }
+            };
+            let features = source.features.filter(|_| use_features);
+            filter
+                .matches(&run.meta)
+                .then_some((run, source.path, features))
+        })
+        .collect()
+}
 fn stat(opts: Stat) {
-    let data =
-        _2b2q::load_csv_dir(opts.data_dir).expect("problem loading data from supplied directory");
+    let data = load_runs(&opts.data_dir, &opts.data, None);
 
     let nets = opts
         .models
         .iter()
-        .map(|path| (path.to_str().unwrap(), _2b2q::load_model(path)))
+        .map(|path| {
+            let predictor = Predictor::load(path).unwrap_or_else(|e| exit_with(e));
+            opts.data.check_features(path, predictor.features());
+            (path.to_str().unwrap(), predictor)
+        })
         .collect::<Vec<_>>();
 
     let logging_data_points = data
-        .flatten()
-        .map(|(x, p)| LoggingDataPoint::from_run(&x, p))
+        .into_iter()
+        .map(|(x, p, _)| LoggingDataPoint::from_run(&x, p))
         .collect::<Vec<_>>();
 
-    let borrowed = nets.iter().map(|x| (x.0, &x.1)).collect::<Vec<_>>();
+    let borrowed = nets
+        .iter()
+        .map(|(name, x)| (*name, x as &dyn EtaModel))
+        .chain([("old", &OldEta as &dyn EtaModel)])
+        .collect::<Vec<_>>();
 
     _2b2q::nn::log(&borrowed[..], &logging_data_points[..])
 }
@@ -178,58 +676,536 @@ fn train(mut opts: Train) {
         opts.r#loop = false;
     }
 
-    let mut net = _2b2q::load_model(&opts.model);
+    let mut model = Model::load(&opts.model).unwrap_or_else(|e| exit_with(e));
+    let extractor = model.meta.extractor().unwrap_or_else(|e| exit_with(e));
+    let features = &*extractor;
+    opts.data.check_features(&opts.model, features);
 
-    let data =
-        _2b2q::load_csv_dir(opts.data_dir).expect("problem loading data from supplied directory");
+    let target = model.meta.target;
+    let examples = Examples::load(
+        &opts.data_dir,
+        &opts.data,
+        opts.split.as_ref(),
+        opts.validation_dir.as_deref(),
+        features,
+        target,
+    );
 
-    let mut logging_data_points = vec![];
-    let training_data_points: Vec<_> = {
-        let mut training_runs = vec![];
-        for (run, p) in data.flatten() {
-            logging_data_points.push(LoggingDataPoint::from_run(&run, p));
-            training_runs.extend(run);
-        }
-        training_runs
-            .into_par_iter()
-            .map(|point| (make_inputs(&point), make_expected_result(&point)))
-            .collect()
+    let halt = if let Some(mse) = opts.mse {
+        Halt::Mse(mse)
+    } else if let Some(epochs) = opts.epochs {
+        Halt::Epochs(epochs)
+    } else {
+        Halt::Timer(std::time::Duration::from_secs(opts.timer.unwrap_or(10)))
     };
-
-    let halt_condition = {
-        use ::nn::HaltCondition::*;
-        if let Some(mse) = opts.mse {
-            MSE(mse)
-        } else if let Some(epochs) = opts.epochs {
-            Epochs(epochs)
-        } else {
-            Timer(
-                chrono::Duration::from_std(std::time::Duration::from_secs(
-                    opts.timer.unwrap_or(10),
-                ))
-                .expect("timer above limit"),
-            )
-        }
+    let optimizer = match &*opts.optimizer {
+        "sgd" => Optimizer::Sgd {
+            momentum: opts.momentum,
+        },
+        _ => Optimizer::Adam,
     };
 
+    let mut best = examples.error_minutes(&model);
+    // an estimator swapped in by `--model-type` outputs zero until its first
+    // fit, so the model it replaced is logged in the first iteration instead
+    let mut previous = None;
+    if let Some(model_type) = opts.model_type {
+        if model_type != model.estimator.model_type() {
+            let (inputs, outputs) = (features.input_count(), target.output_count());
+            previous = Some(model.clone());
+            model.estimator = Estimator::untrained(model_type, inputs, outputs)
+                .unwrap_or_else(|| exit_with("networks are created with `new`"));
+            model.meta.layers = vec![inputs, outputs];
+            model.meta.training = None;
+            model.meta.intervals = None;
+        }
+    }
+    let mut scheduler = Scheduler::new(
+        opts.schedule,
+        opts.rate.unwrap_or(match model.estimator {
+            Estimator::Network(_) => optimizer.default_rate(),
+            _ => 0.3,
+        }),
+    );
+    // the trainer keeps the moments of the optimiser across iterations
+    let mut trainer = None;
+    let mut stale = 0;
+    let mut iterations = 0;
     loop {
+        let previous = previous.take();
         if opts.logging {
-            log(&[("new", &net)], &logging_data_points)
+            let (name, logged) = match previous {
+                Some(previous) => ("previous", previous),
+                None => ("new", model.clone()),
+            };
+            let predictor = Predictor::with_features(logged, extractor.clone());
+            log(&[(name, &predictor), ("old", &OldEta)], &examples.logging)
         }
 
-        net.train(&training_data_points)
-            .halt_condition(halt_condition)
-            .log_interval(opts.logging_err_rate)
-            .momentum(opts.momentum)
-            .rate(opts.rate)
-            .go();
+        let rate = scheduler.rate();
+        match &mut model.estimator {
+            Estimator::Network(net) => {
+                let options = TrainOptions {
+                    optimizer,
+                    rate,
+                    batch_size: opts.batch_size,
+                    weight_decay: opts.weight_decay,
+                    halt,
+                    log_interval: opts.logging_err_rate,
+                };
+                let trainer = trainer.get_or_insert_with(|| Trainer::new(net, options));
+                trainer.set_rate(rate);
+                trainer.train(net, &examples.training);
+            }
+            Estimator::Ridge(ridge) => {
+                let (inputs, outputs) = (features.input_count(), target.output_count());
+                *ridge = Ridge::fit(&examples.training, inputs, outputs, opts.lambda)
+            }
+            Estimator::Boosted(boosted) => boosted.grow(
+                &examples.training,
+                GrowOptions {
+                    trees: opts.trees,
+                    depth: opts.depth,
+                    rate,
+                },
+            ),
+        }
+        iterations += 1;
 
-        BufWriter::new(File::create(&opts.model).unwrap())
-            .write_all(net.to_json().as_bytes())
-            .ok();
+        let error = examples.error_minutes(&model);
+        scheduler.advance(error < best);
+        if error < best {
+            eprintln!("error improved from {best:.1}m to {error:.1}m, saving model");
+            let network = matches!(model.estimator, Estimator::Network(_));
+            let meta = examples.record_training(
+                &mut model,
+                iterations,
+                opts.epochs.filter(|_| network),
+                Some(optimizer).filter(|_| network),
+                rate,
+                error,
+            );
+            model.save(&opts.model).unwrap_or_else(|e| exit_with(e));
+            if opts.checkpoints {
+                let checkpoint =
+                    checkpoint::save(&opts.model, &model, &meta).unwrap_or_else(|e| exit_with(e));
+                eprintln!("kept checkpoint {:?}", checkpoint.path);
+            }
+            best = error;
+            stale = 0;
+        } else {
+            stale += 1;
+            eprintln!("error of {error:.1}m is not below {best:.1}m, {stale} iterations without improvement");
+            if matches!(opts.patience, Some(patience) if stale >= patience) {
+                eprintln!("stopping early, the model with an error of {best:.1}m is kept");
+                break;
+            }
+        }
 
-        if !opts.r#loop {
+        // refitting a ridge model on the same runs gives the same weights
+        if !opts.r#loop || matches!(model.estimator, Estimator::Ridge(_)) {
             break;
         }
     }
 }
+fn sweep(opts: Sweep) {
+    let extractor = opts.data.features.clone().unwrap_or_else(features::default);
+    let features = &*extractor;
+    for layers in &opts.layers {
+        if let Err(message) = model::check_layers(features, opts.target, layers) {
+            exit_with(format!("refusing to sweep {layers:?}: {message}"));
+        }
+    }
+    let optimizers: Vec<_> = opts
+        .optimizer
+        .iter()
+        .flat_map(|name| match &**name {
+            "sgd" => opts
+                .momentum
+                .iter()
+                .map(|&momentum| Optimizer::Sgd { momentum })
+                .collect(),
+            _ => vec![Optimizer::Adam],
+        })
+        .collect();
+    let mut configs = sweep::grid(&opts.layers, &optimizers, &opts.rate);
+    if let Some(samples) = opts.samples {
+        configs = sweep::sample(configs, samples, opts.seed);
+    }
+
+    let examples = Examples::load(
+        &opts.data_dir,
+        &opts.data,
+        opts.split.as_ref(),
+        opts.validation_dir.as_deref(),
+        features,
+        opts.target,
+    );
+    let (error_data_points, error_points) = examples.error();
+
+    let options = SweepOptions {
+        activation: opts.activation,
+        target: opts.target,
+        batch_size: opts.batch_size,
+        weight_decay: opts.weight_decay,
+        halt: match opts.timer {
+            Some(timer) => Halt::Timer(std::time::Duration::from_secs(timer)),
+            None => Halt::Epochs(opts.epochs.unwrap_or(10)),
+        },
+        iterations: opts.iterations,
+        schedule: opts.schedule,
+    };
+    eprintln!("training {} networks", configs.len());
+    let mut outcomes: Vec<_> = configs
+        .par_iter()
+        .map(|config| {
+            let outcome = sweep::run(
+                config,
+                &options,
+                features,
+                &examples.training,
+                error_data_points,
+                error_points,
+            );
+            eprintln!(
+                "{} {} at rate {}: {:.1}m after {} iterations",
+                model::layers_to_string(&config.layers),
+                config.optimizer.name(),
+                config.rate as f32,
+                outcome.error_minutes,
+                outcome.iteration
+            );
+            outcome
+        })
+        .collect();
+    sweep::rank(&mut outcomes);
+
+    if let Some(dir) = &opts.dir {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| exit_with(Error::from(e).in_file(dir)));
+        for outcome in &mut outcomes {
+            let config = &outcome.config;
+            examples.record_training(
+                &mut outcome.model,
+                outcome.iteration,
+                opts.epochs,
+                Some(config.optimizer),
+                config.rate,
+                outcome.error_minutes,
+            );
+            let mut name = format!(
+                "{}-{}-{}",
+                model::layers_to_string(&config.layers),
+                config.optimizer.name(),
+                config.rate
+            );
+            if let Optimizer::Sgd { momentum } = config.optimizer {
+                name += &format!("-{momentum}");
+            }
+            let path = dir.join(name + ".json");
+            outcome.model.save(&path).unwrap_or_else(|e| exit_with(e));
+        }
+    }
+
+    sweep::write_table(&outcomes, std::io::stdout().lock()).unwrap_or_else(|e| exit_with(e));
+    if let Some(output) = &opts.output {
+        std::fs::File::create(output)
+            .and_then(|file| sweep::write_table(&outcomes, std::io::BufWriter::new(file)))
+            .unwrap_or_else(|e| exit_with(Error::from(e).in_file(output)));
+    }
+}
+/// examples of the training runs and of the held-out runs the error is
+/// measured on
+struct Examples {
+    training: Features,
+    training_points: Vec<TrainingDataPoint>,
+    validation: Features,
+    validation_points: Vec<TrainingDataPoint>,
+    /// hash of the training runs, see `checkpoint::hash_runs`
+    dataset: u64,
+    /// the start of every run the error is measured on, for `log`
+    logging: Vec<LoggingDataPoint>,
+}
+impl Examples {
+    /// loads the runs in the directory and divides them into training and
+    /// validation runs by the split and the runs in `validation_dir`, exits if
+    /// there is nothing to train on
+    fn load(
+        data_dir: &Path,
+        opts: &DataOptions,
+        split: Option<&Split>,
+        validation_dir: Option<&Path>,
+        features: &dyn FeatureExtractor,
+        target: Target,
+    ) -> Self {
+        let (data, validation) = load_split(data_dir, opts, split, validation_dir, features);
+        let dataset = checkpoint::hash_runs(data.iter().map(|(run, _, _)| run));
+        let logging = match validation.is_empty() {
+            true => &data,
+            false => &validation,
+        }
+        .iter()
+        .map(|(run, p, _)| LoggingDataPoint::from_run(run, p.clone()))
+        .collect();
+        let (validation, validation_points) = examples(validation, features, target);
+        let (training, training_points) = examples(data, features, target);
+        if training.is_empty() {
+            exit_with("no data points to train on, every run was filtered out or held out");
+        }
+        Examples {
+            training,
+            training_points,
+            validation,
+            validation_points,
+            dataset,
+            logging,
+        }
+    }
+    /// whether the error is measured on held-out runs
+    fn validated(&self) -> bool {
+        !self.validation.is_empty()
+    }
+    /// the examples the error is measured on together with their data points,
+    /// the training examples if there are no held-out ones
+    fn error(&self) -> (&Features, &[TrainingDataPoint]) {
+        match self.validated() {
+            true => (&self.validation, &self.validation_points),
+            false => (&self.training, &self.training_points),
+        }
+    }
+    fn error_minutes(&self, model: &Model) -> f64 {
+        let (examples, points) = self.error();
+        error_minutes(&model.estimator, model.meta.target, examples, points)
+    }
+    /// stores how the model was trained and the intervals of its residuals in
+    /// the model, `epochs` is the amount per iteration and `optimizer` is only
+    /// known for networks
+    fn record_training(
+        &self,
+        model: &mut Model,
+        iterations: u32,
+        epochs: Option<u32>,
+        optimizer: Option<Optimizer>,
+        rate: f64,
+        error: f64,
+    ) -> TrainingMeta {
+        let meta = TrainingMeta {
+            created: chrono::Utc::now().timestamp_millis() as u64,
+            iterations,
+            epochs: epochs.map(|x| x as u64 * iterations as u64),
+            optimizer: optimizer.map(|x| x.name().into()),
+            momentum: optimizer.and_then(Optimizer::momentum),
+            rate,
+            dataset: self.dataset,
+            error_minutes: error,
+            validated: self.validated(),
+        };
+        model.meta.training = Some(meta.clone());
+        let (examples, points) = self.error();
+        let residuals = residual_minutes(&model.estimator, model.meta.target, examples, points);
+        model.meta.intervals = Intervals::from_residuals(
+            points
+                .iter()
+                .map(|point| point.current_position)
+                .zip(residuals),
+        );
+        meta
+    }
+}
+/// loads the runs in the directory and divides them into training and
+/// validation runs by the split and the runs in `validation_dir`
+fn load_split(
+    data_dir: &Path,
+    opts: &DataOptions,
+    split: Option<&Split>,
+    validation_dir: Option<&Path>,
+    features: &dyn FeatureExtractor,
+) -> (LoadedRuns, LoadedRuns) {
+    let data = load_runs(data_dir, opts, Some(features));
+    let (data, mut validation) = match split {
+        Some(split) => split.apply(data, |(run, path, _)| (run, path)),
+        None => (data, vec![]),
+    };
+    if let Some(dir) = validation_dir {
+        validation.extend(load_runs_from(
+            &[dir.to_path_buf()],
+            opts,
+            None,
+            Some(features),
+        ));
+    }
+    if !validation.is_empty() {
+        eprintln!(
+            "training on {} runs, validating on {} runs",
+            data.len(),
+            validation.len()
+        );
+    }
+    (data, validation)
+}
+/// computes the network inputs and expected outputs of every data point
+/// together with the data points themselves, using the cached inputs of a run
+/// where present
+fn examples(
+    runs: LoadedRuns,
+    features: &dyn FeatureExtractor,
+    target: Target,
+) -> (Features, Vec<TrainingDataPoint>) {
+    let mut examples = vec![];
+    let mut points = vec![];
+    let mut uncached = vec![];
+    for (run, _, cached) in runs {
+        match cached {
+            Some(cached) => {
+                for (point, (inputs, _)) in run.into_iter().zip(cached) {
+                    examples.push((inputs, target.encode(&point)));
+                    points.push(point);
+                }
+            }
+            None => uncached.extend(run),
+        }
+    }
+    let computed: Vec<_> = uncached
+        .par_iter()
+        .map(|point| (features.inputs(point), target.encode(point)))
+        .collect();
+    examples.extend(computed);
+    points.extend(uncached);
+    (examples, points)
+}
+fn predict(opts: Predict) {
+    let point = TrainingDataPoint {
+        start_time: opts.start_time,
+        start_position: opts.start_position,
+        start_length: opts.start_length.unwrap_or(opts.length),
+        current_time: opts
+            .time
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_millis() as u64),
+        current_position: opts.position,
+        current_length: opts.length,
+        recent: opts
+            .recent_time
+            .zip(opts.recent_position)
+            .map(|(time, position)| QueueDataPoint {
+                time,
+                position,
+                length: opts.length,
+            }),
+        expected_output: 0,
+    };
+
+    println!("pred\tp10\tp90\tmodel");
+    for path in &opts.models {
+        let predictor = Predictor::load(path).unwrap_or_else(|e| exit_with(e));
+        let result_h = predictor.predict(&point).as_secs_f64() / 3600.0;
+        let interval = match predictor.predict_interval(&point) {
+            Some(x) => format!(
+                "{:.2}h\t{:.2}h",
+                x.p10.as_secs_f64() / 3600.0,
+                x.p90.as_secs_f64() / 3600.0
+            ),
+            None => "-\t-".into(),
+        };
+        println!("{result_h:.2}h\t{interval}\t{}", path.to_str().unwrap());
+    }
+    let old_pred_h = _2b2q::old_eta(opts.position, opts.length) / 3600.0;
+    println!("{old_pred_h:.2}h\t-\t-\told");
+}
+fn check(opts: Check) {
+    let max_gap = std::time::Duration::from_secs_f64(opts.max_gap * 60.0);
+    let options = opts.format.load_options();
+    let roots = opts.dirs.roots(&opts.data_dir);
+    let results = _2b2q::check::check_dirs(&roots, &opts.dirs.walk_options(), &options, max_gap)
+        .unwrap_or_else(|e| exit_with(e));
+
+    let mut bad_files = 0;
+    for (path, problems) in &results {
+        match problems {
+            Ok(problems) if problems.is_empty() => continue,
+            Ok(problems) => problems.iter().for_each(|p| println!("{path:?}: {p}")),
+            Err(e) => println!("{path:?}: {e}"),
+        }
+        bad_files += 1;
+    }
+    println!("{bad_files} of {} files have problems", results.len());
+    if bad_files > 0 {
+        std::process::exit(1);
+    }
+}
+fn import(opts: Import) {
+    let max_gap = std::time::Duration::from_secs_f64(opts.max_gap * 60.0);
+    std::fs::create_dir_all(&opts.out_dir).unwrap_or_else(|e| exit_with(e));
+
+    for path in &opts.logs {
+        match _2b2q::logs::convert_log_file(path, &opts.out_dir, max_gap) {
+            Ok(runs) => println!("{path:?}: {runs} runs"),
+            Err(e) => eprintln!("skipping {e}"),
+        }
+    }
+}
+fn dataset_build(opts: Build) {
+    let options = opts.format.load_options();
+    let roots = opts.dirs.roots(&opts.data_dir);
+    let dataset = dataset::Dataset::build(
+        &roots,
+        &opts.dirs.walk_options(),
+        &options,
+        opts.features.as_deref(),
+        Some(&opts.cache),
+    )
+    .unwrap_or_else(|e| exit_with(e));
+    dataset.write(&opts.cache).unwrap_or_else(|e| exit_with(e));
+
+    let mut runs = 0;
+    let mut points = 0;
+    for source in dataset.sources() {
+        match &source.run {
+            Ok(run) => {
+                runs += 1;
+                points += run.point_count();
+            }
+            Err(message) => eprintln!("skipping {:?}: {message}", source.path),
+        }
+    }
+    println!(
+        "cached {runs} runs with {points} data points from {} files",
+        dataset.sources().len()
+    );
+}
+fn checkpoints_list(opts: ListCheckpoints) {
+    let checkpoints = checkpoint::list(&opts.model).unwrap_or_else(|e| exit_with(e));
+    println!("time\t\t\terror\titers\tepochs\toptim\tmomentum\trate\tdataset");
+    for checkpoint in &checkpoints {
+        match &checkpoint.meta {
+            Ok(meta) => println!(
+                "{}\t{:.1}m{}\t{}\t{}\t{}\t{}\t{}\t{:016x}",
+                checkpoint.time,
+                meta.error_minutes,
+                if meta.validated { "" } else { "*" },
+                meta.iterations,
+                meta.epochs.map_or("?".into(), |x| x.to_string()),
+                meta.optimizer.as_deref().unwrap_or("-"),
+                meta.momentum.map_or("-".into(), |x| (x as f32).to_string()),
+                meta.rate as f32,
+                meta.dataset
+            ),
+            Err(message) => println!("{}\tmissing metadata: {message}", checkpoint.time),
+        }
+    }
+    if checkpoints
+        .iter()
+        .any(|x| matches!(&x.meta, Ok(meta) if !meta.validated))
+    {
+        println!("* error on the training runs as there were no held-out runs");
+    }
+}
+fn checkpoints_restore(opts: RestoreCheckpoint) {
+    let checkpoints = checkpoint::list(&opts.model).unwrap_or_else(|e| exit_with(e));
+    let checkpoint = checkpoint::find(&checkpoints, &opts.checkpoint).unwrap_or_else(|| {
+        exit_with(format!(
+            "no checkpoint of {:?} matches {:?}",
+            opts.model, opts.checkpoint
+        ))
+    });
+    checkpoint::restore(&opts.model, checkpoint).unwrap_or_else(|e| exit_with(e));
+    println!("restored {:?} from {:?}", opts.model, checkpoint.path);
+}
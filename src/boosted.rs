@@ -0,0 +1,292 @@
+//! gradient boosted regression trees for the first output of a target
+//!
+//! every tree is fitted to what the previous ones got wrong under squared
+//! error, splits are searched on at most `BINS` quantiles of every input
+
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+use serde_json::{json, Map, Value};
+
+/// amount of candidate thresholds per input
+pub const BINS: usize = 64;
+/// leaves hold at least this many examples
+pub const MIN_LEAF: usize = 20;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Boosted {
+    /// prediction before any tree, the mean of the first examples grown on
+    pub base: f64,
+    pub trees: Vec<Tree>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tree {
+    /// the root is the first node
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// inputs with `inputs[feature] <= threshold` continue at the node
+    /// `left`, others at `right`
+    Split {
+        feature: usize,
+        threshold: f64,
+        left: usize,
+        right: usize,
+    },
+    /// value added to the prediction, already scaled by the learning rate
+    Leaf(f64),
+}
+
+/// how `Boosted::grow` fits new trees
+#[derive(Clone, Copy, Debug)]
+pub struct GrowOptions {
+    pub trees: usize,
+    pub depth: usize,
+    /// factor the values of the leaves are scaled by
+    pub rate: f64,
+}
+
+impl Boosted {
+    pub fn run(&self, inputs: &[f64]) -> Vec<f64> {
+        vec![self.base + self.trees.iter().map(|x| x.run(inputs)).sum::<f64>()]
+    }
+    /// adds `options.trees` trees fitted to the examples
+    pub fn grow(&mut self, examples: &[(Vec<f64>, Vec<f64>)], options: GrowOptions) {
+        if examples.is_empty() {
+            return;
+        }
+        let expected: Vec<f64> = examples.iter().map(|(_, y)| y[0]).collect();
+        if self.trees.is_empty() {
+            self.base = expected.iter().sum::<f64>() / expected.len() as f64;
+        }
+        let bins = Bins::new(examples);
+        let mut predicted: Vec<f64> = examples.par_iter().map(|(x, _)| self.run(x)[0]).collect();
+        for _ in 0..options.trees {
+            let residuals: Vec<f64> = expected
+                .iter()
+                .zip(&predicted)
+                .map(|(y, p)| y - p)
+                .collect();
+            let mut tree = Tree { nodes: vec![] };
+            let all = (0..examples.len() as u32).collect();
+            tree.build(&bins, &residuals, all, options.depth, options.rate);
+            predicted
+                .par_iter_mut()
+                .zip(examples)
+                .for_each(|(p, (x, _))| *p += tree.run(x));
+            self.trees.push(tree);
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("base".into(), json!(self.base));
+        o.insert(
+            "trees".into(),
+            Value::Array(self.trees.iter().map(Tree::to_json).collect()),
+        );
+        Value::Object(o)
+    }
+    pub(crate) fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        Ok(Boosted {
+            base: json
+                .get("base")
+                .and_then(Value::as_f64)
+                .ok_or("invalid base")?,
+            trees: json
+                .get("trees")
+                .and_then(Value::as_array)
+                .ok_or("invalid trees")?
+                .iter()
+                .map(Tree::from_json)
+                .collect::<std::result::Result<_, _>>()?,
+        })
+    }
+}
+
+impl Tree {
+    pub fn run(&self, inputs: &[f64]) -> f64 {
+        let mut node = 0;
+        loop {
+            match self.nodes[node] {
+                Node::Split {
+                    feature,
+                    threshold,
+                    left,
+                    right,
+                } => {
+                    // inputs the tree doesn't know go right like nan
+                    node = match inputs.get(feature) {
+                        Some(x) if *x <= threshold => left,
+                        _ => right,
+                    }
+                }
+                Node::Leaf(value) => return value,
+            }
+        }
+    }
+    /// adds a node fitting the residuals of the examples at `indices` and
+    /// returns its index
+    fn build(
+        &mut self,
+        bins: &Bins,
+        residuals: &[f64],
+        indices: Vec<u32>,
+        depth: usize,
+        rate: f64,
+    ) -> usize {
+        let index = self.nodes.len();
+        let mean =
+            indices.iter().map(|&i| residuals[i as usize]).sum::<f64>() / indices.len() as f64;
+        self.nodes.push(Node::Leaf(mean * rate));
+        let split = match depth {
+            0 => None,
+            _ => bins.best_split(residuals, &indices),
+        };
+        if let Some((feature, bin)) = split {
+            let (left, right) = indices
+                .into_iter()
+                .partition(|&i| bins.bins[feature][i as usize] as usize <= bin);
+            let left = self.build(bins, residuals, left, depth - 1, rate);
+            let right = self.build(bins, residuals, right, depth - 1, rate);
+            self.nodes[index] = Node::Split {
+                feature,
+                threshold: bins.thresholds[feature][bin],
+                left,
+                right,
+            };
+        }
+        index
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Array(
+            self.nodes
+                .iter()
+                .map(|node| {
+                    let mut o = Map::new();
+                    match *node {
+                        Node::Split {
+                            feature,
+                            threshold,
+                            left,
+                            right,
+                        } => {
+                            o.insert("feature".into(), json!(feature));
+                            o.insert("threshold".into(), json!(threshold));
+                            o.insert("left".into(), json!(left));
+                            o.insert("right".into(), json!(right));
+                        }
+                        Node::Leaf(value) => {
+                            o.insert("value".into(), json!(value));
+                        }
+                    }
+                    Value::Object(o)
+                })
+                .collect(),
+        )
+    }
+    fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        let nodes = json.as_array().ok_or("invalid tree")?;
+        // children come after their parent so a tree can't loop
+        let child = |node: &Value, parent, key| {
+            node.get(key)
+                .and_then(Value::as_u64)
+                .map(|x| x as usize)
+                .filter(|&x| x > parent && x < nodes.len())
+                .ok_or_else(|| format!("invalid {key}"))
+        };
+        let nodes = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| match node.get("value") {
+                Some(value) => Ok(Node::Leaf(value.as_f64().ok_or("invalid value")?)),
+                None => Ok(Node::Split {
+                    feature: node
+                        .get("feature")
+                        .and_then(Value::as_u64)
+                        .ok_or("invalid feature")? as usize,
+                    threshold: node
+                        .get("threshold")
+                        .and_then(Value::as_f64)
+                        .ok_or("invalid threshold")?,
+                    left: child(node, i, "left")?,
+                    right: child(node, i, "right")?,
+                }),
+            })
+            .collect::<std::result::Result<Vec<_>, String>>()?;
+        if nodes.is_empty() {
+            return Err("invalid tree".into());
+        }
+        Ok(Tree { nodes })
+    }
+}
+
+/// the inputs of every example replaced by the index of the first threshold
+/// they don't exceed
+struct Bins {
+    /// candidate thresholds of every input, ascending
+    thresholds: Vec<Vec<f64>>,
+    /// bin of every example for every input
+    bins: Vec<Vec<u8>>,
+}
+impl Bins {
+    fn new(examples: &[(Vec<f64>, Vec<f64>)]) -> Self {
+        let inputs = examples[0].0.len();
+        let (thresholds, bins) = (0..inputs)
+            .into_par_iter()
+            .map(|feature| {
+                let mut values: Vec<f64> = examples.iter().map(|(x, _)| x[feature]).collect();
+                values.sort_by(f64::total_cmp);
+                let mut thresholds: Vec<f64> = (1..BINS)
+                    .map(|i| values[i * (values.len() - 1) / BINS])
+                    .collect();
+                thresholds.dedup();
+                let bins = examples
+                    .iter()
+                    .map(|(x, _)| thresholds.partition_point(|&t| t < x[feature]) as u8)
+                    .collect();
+                (thresholds, bins)
+            })
+            .unzip();
+        Bins { thresholds, bins }
+    }
+    /// input and bin of the split reducing the squared error the most,
+    /// `None` if no split leaves `MIN_LEAF` examples on both sides
+    fn best_split(&self, residuals: &[f64], indices: &[u32]) -> Option<(usize, usize)> {
+        let total: f64 = indices.iter().map(|&i| residuals[i as usize]).sum();
+        let count = indices.len();
+        let score = |sum: f64, count: usize| sum * sum / count as f64;
+        (0..self.thresholds.len())
+            .into_par_iter()
+            .filter_map(|feature| {
+                let mut sums = vec![0.0; BINS + 1];
+                let mut counts = vec![0; BINS + 1];
+                for &i in indices {
+                    let bin = self.bins[feature][i as usize] as usize;
+                    sums[bin] += residuals[i as usize];
+                    counts[bin] += 1;
+                }
+                let (mut sum, mut left) = (0.0, 0);
+                (0..self.thresholds[feature].len())
+                    .filter_map(|bin| {
+                        sum += sums[bin];
+                        left += counts[bin];
+                        let right = count - left;
+                        (left >= MIN_LEAF && right >= MIN_LEAF).then(|| {
+                            let gain =
+                                score(sum, left) + score(total - sum, right) - score(total, count);
+                            (gain, feature, bin)
+                        })
+                    })
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .filter(|(gain, _, _)| *gain > 0.0)
+            .map(|(_, feature, bin)| (feature, bin))
+    }
+}
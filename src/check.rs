@@ -0,0 +1,303 @@
+//! validation of queue data files, reports problems which the loaders either
+//! reject or silently accept
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    compress, csv::strip_bom, parse_csv_row, parse_jsonl_row, CsvHeaderItem, Error, LoadOptions,
+    QueueDataPoint, Result, RunMetadata, WalkOptions,
+};
+
+pub enum Problem {
+    /// the file does not contain a single line
+    MissingHeader,
+    /// the header contains a column which is not known
+    UnknownColumn(String),
+    /// the header contains the same column more than once
+    DuplicateColumn(String),
+    /// the header lacks a required column
+    MissingColumn(&'static str),
+    /// the file does not contain any data rows
+    Empty,
+    InvalidRow {
+        line: usize,
+        message: String,
+    },
+    TimeNotIncreasing {
+        line: usize,
+    },
+    PositionIncreased {
+        line: usize,
+        from: u16,
+        to: u16,
+    },
+    PositionAboveLength {
+        line: usize,
+        position: u16,
+        length: u16,
+    },
+    Gap {
+        line: usize,
+        duration: Duration,
+    },
+    DuplicateRow {
+        line: usize,
+        first: usize,
+    },
+    /// the queue, server or version differs from an earlier row, the loaders
+    /// reject the whole file
+    MetadataChanged {
+        line: usize,
+        message: String,
+    },
+    /// the last data row does not have position 0
+    NeverReachesZero {
+        last_position: u16,
+    },
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Problem::*;
+        match self {
+            MissingHeader => write!(f, "file is empty"),
+            UnknownColumn(s) => write!(f, "unknown column {s:?}"),
+            DuplicateColumn(s) => write!(f, "duplicate column {s:?}"),
+            MissingColumn(s) => write!(f, "missing column {s:?}"),
+            Empty => write!(f, "no data rows"),
+            InvalidRow { line, message } => write!(f, "line {line}: {message}"),
+            TimeNotIncreasing { line } => write!(f, "line {line}: time is not increasing"),
+            PositionIncreased { line, from, to } => {
+                write!(f, "line {line}: position increased from {from} to {to}")
+            }
+            PositionAboveLength {
+                line,
+                position,
+                length,
+            } => write!(f, "line {line}: position {position} above length {length}"),
+            Gap { line, duration } => write!(
+                f,
+                "line {line}: gap of {:.1} minutes",
+                duration.as_secs_f64() / 60.0
+            ),
+            DuplicateRow { line, first } => write!(f, "line {line}: duplicate of line {first}"),
+            MetadataChanged { line, message } => write!(f, "line {line}: {message}"),
+            NeverReachesZero { last_position } => {
+                write!(
+                    f,
+                    "never reaches position 0, last position is {last_position}"
+                )
+            }
+        }
+    }
+}
+
+/// checks a queue data csv file, gaps between rows longer than `max_gap` are
+/// reported
+pub fn check_csv_file(
+    f: impl Read,
+    options: &LoadOptions,
+    max_gap: Duration,
+) -> Result<Vec<Problem>> {
+    let mut problems = vec![];
+    let mut lines = BufReader::new(f).lines().enumerate();
+
+    let (header, csv) = loop {
+        let line = match lines.next() {
+            Some((_, line)) => line?,
+            None => {
+                problems.push(Problem::MissingHeader);
+                return Ok(problems);
+            }
+        };
+        let line = strip_bom(&line);
+        if line.trim().is_empty() || options.csv.is_comment(line) {
+            continue;
+        }
+        let csv = options.csv.detect_delimiter(line);
+        let mut header: Vec<Option<CsvHeaderItem>> = vec![];
+        for s in csv.split(line) {
+            let item = CsvHeaderItem::from_str(&s);
+            match item {
+                None => problems.push(Problem::UnknownColumn(s)),
+                Some(_) if header.contains(&item) => problems.push(Problem::DuplicateColumn(s)),
+                Some(_) => {}
+            }
+            header.push(item);
+        }
+        break (header, csv);
+    };
+    for item in [
+        CsvHeaderItem::Time,
+        CsvHeaderItem::Position,
+        CsvHeaderItem::Length,
+    ] {
+        if !header.contains(&Some(item)) {
+            problems.push(Problem::MissingColumn(item.name()));
+        }
+    }
+    // rows can't be checked without knowing what any of their values mean
+    if header.iter().all(Option::is_none) {
+        return Ok(problems);
+    }
+
+    let options = &LoadOptions {
+        csv,
+        ..options.clone()
+    };
+    let rows = lines.map(|(n, line)| line.map(|line| (n + 1, line)));
+    check_rows(
+        rows,
+        max_gap,
+        |line| match options.csv.is_comment(line) {
+            true => Ok(None),
+            false => parse_csv_row(options, &header, line).map(Some),
+        },
+        problems,
+    )
+}
+
+/// checks a file containing one json object per line, gaps between rows
+/// longer than `max_gap` are reported
+pub fn check_jsonl_file(
+    f: impl Read,
+    options: &LoadOptions,
+    max_gap: Duration,
+) -> Result<Vec<Problem>> {
+    let rows = BufReader::new(f)
+        .lines()
+        .enumerate()
+        .map(|(n, line)| line.map(|line| (n + 1, line)));
+    check_rows(
+        rows,
+        max_gap,
+        |line| parse_jsonl_row(options, line).map(Some),
+        vec![],
+    )
+}
+
+fn check_rows(
+    rows: impl Iterator<Item = std::io::Result<(usize, String)>>,
+    max_gap: Duration,
+    mut parse: impl FnMut(&str) -> std::result::Result<Option<(QueueDataPoint, RunMetadata)>, String>,
+    mut problems: Vec<Problem>,
+) -> Result<Vec<Problem>> {
+    let mut seen = HashMap::new();
+    let mut run_meta = RunMetadata::default();
+    let mut last: Option<QueueDataPoint> = None;
+    for row in rows {
+        let (line_number, line) = row?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let point = match parse(&line) {
+            Ok(Some((point, meta))) => {
+                if let Err(message) = run_meta.merge(meta.clone()) {
+                    problems.push(Problem::MetadataChanged {
+                        line: line_number,
+                        message,
+                    });
+                    // later rows are compared with the new values so every
+                    // change is reported once
+                    let RunMetadata {
+                        queue,
+                        server,
+                        client_version,
+                    } = meta;
+                    run_meta.queue = queue.or(run_meta.queue);
+                    run_meta.server = server.or(run_meta.server.take());
+                    run_meta.client_version = client_version.or(run_meta.client_version.take());
+                }
+                point
+            }
+            Ok(None) => continue,
+            Err(message) => {
+                problems.push(Problem::InvalidRow {
+                    line: line_number,
+                    message,
+                });
+                continue;
+            }
+        };
+
+        if let Some(&first) = seen.get(&point) {
+            problems.push(Problem::DuplicateRow {
+                line: line_number,
+                first,
+            });
+        } else {
+            seen.insert(point, line_number);
+        }
+        if point.position > point.length {
+            problems.push(Problem::PositionAboveLength {
+                line: line_number,
+                position: point.position,
+                length: point.length,
+            });
+        }
+        if let Some(last) = last {
+            if point.time <= last.time {
+                problems.push(Problem::TimeNotIncreasing { line: line_number });
+            } else if point.time - last.time > max_gap.as_millis() as u64 {
+                problems.push(Problem::Gap {
+                    line: line_number,
+                    duration: Duration::from_millis(point.time - last.time),
+                });
+            }
+            if point.position > last.position {
+                problems.push(Problem::PositionIncreased {
+                    line: line_number,
+                    from: last.position,
+                    to: point.position,
+                });
+            }
+        }
+        last = Some(point);
+    }
+
+    match last {
+        None => problems.push(Problem::Empty),
+        Some(last) if last.position != 0 => problems.push(Problem::NeverReachesZero {
+            last_position: last.position,
+        }),
+        Some(_) => {}
+    }
+    Ok(problems)
+}
+
+/// checks every file in a directory, sorted by path, files are checked as
+/// csv unless their extension says otherwise
+pub fn check_dir(
+    p: impl AsRef<Path>,
+    options: &LoadOptions,
+    max_gap: Duration,
+) -> Result<Vec<(PathBuf, Result<Vec<Problem>>)>> {
+    check_dirs(&[p], &WalkOptions::default(), options, max_gap)
+}
+/// checks the files of several directories selected by `walk`
+pub fn check_dirs(
+    roots: &[impl AsRef<Path>],
+    walk: &WalkOptions,
+    options: &LoadOptions,
+    max_gap: Duration,
+) -> Result<Vec<(PathBuf, Result<Vec<Problem>>)>> {
+    Ok(walk
+        .paths(roots)?
+        .into_iter()
+        .map(|path| {
+            let check = match compress::data_extension(&path) {
+                Some("jsonl" | "ndjson") => check_jsonl_file,
+                _ => check_csv_file,
+            };
+            let problems = compress::open(&path)
+                .map_err(Error::from)
+                .and_then(|f| check(f, options, max_gap));
+            (path, problems)
+        })
+        .collect())
+}
@@ -0,0 +1,150 @@
+//! timestamped copies of a model kept by `train --checkpoints`
+//!
+//! the checkpoints of `models/10-4-1.json` are named like
+//! `models/10-4-1.2026-10-16T12:00:00.000.json`, each with a sidecar
+//! `models/10-4-1.2026-10-16T12:00:00.000.meta.json` describing how it was
+//! trained, checkpoints saved within the same millisecond get a suffix like
+//! `-1` and existing checkpoints are never replaced
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::{model::TrainingMeta, split::fnv1a, write_atomic, Error, Model, QueueRun, Result};
+
+/// format of the time in the name of a checkpoint, in utc
+pub const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+/// format of checkpoints written before they had milliseconds
+const SECONDS_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// a checkpoint of a model found next to it
+pub struct Checkpoint {
+    /// time in the name of the checkpoint, formatted with `TIME_FORMAT` and
+    /// followed by the suffix if there is one
+    pub time: String,
+    pub path: PathBuf,
+    /// the sidecar or the reason it could not be read
+    pub meta: std::result::Result<TrainingMeta, String>,
+}
+
+/// writes `model` as a new checkpoint of the model at `path`, named after
+/// `meta.created`
+pub fn save(path: &Path, model: &Model, meta: &TrainingMeta) -> Result<Checkpoint> {
+    let time = DateTime::from_timestamp_millis(meta.created as i64)
+        .unwrap_or_default()
+        .format(TIME_FORMAT)
+        .to_string();
+    let time = (0..)
+        .map(|n| match n {
+            0 => time.clone(),
+            n => format!("{time}-{n}"),
+        })
+        .find(|time| {
+            !checkpoint_path(path, time, "json").exists()
+                && !checkpoint_path(path, time, "meta.json").exists()
+        })
+        .expect("some suffix is unused");
+    let checkpoint = checkpoint_path(path, &time, "json");
+    model.save(&checkpoint)?;
+    write_atomic(&checkpoint_path(path, &time, "meta.json"), |w| {
+        write!(w, "{}", meta.to_json())
+    })?;
+    Ok(Checkpoint {
+        time,
+        path: checkpoint,
+        meta: Ok(meta.clone()),
+    })
+}
+
+/// lists the checkpoints of the model at `model`, oldest first
+pub fn list(model: &Path) -> Result<Vec<Checkpoint>> {
+    let dir = match model.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.", stem(model));
+    let entries = std::fs::read_dir(dir)
+        .and_then(|rd| rd.collect::<std::io::Result<Vec<_>>>())
+        .map_err(|e| Error::from(e).in_file(dir))?;
+    let mut checkpoints: Vec<_> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let time = name.strip_prefix(&prefix)?.strip_suffix(".json")?;
+            let key = sort_key(time)?;
+            let meta = std::fs::read_to_string(checkpoint_path(model, time, "meta.json"))
+                .map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+                .and_then(|json| TrainingMeta::from_json(&json));
+            Some((
+                key,
+                Checkpoint {
+                    time: time.into(),
+                    path: entry.path(),
+                    meta,
+                },
+            ))
+        })
+        .collect();
+    checkpoints.sort_by_key(|(key, _)| *key);
+    Ok(checkpoints.into_iter().map(|(_, x)| x).collect())
+}
+
+/// finds a checkpoint by `latest`, `best` or the start of its time, for
+/// example `2026-10-13`, the latest matching checkpoint is picked
+pub fn find<'a>(checkpoints: &'a [Checkpoint], query: &str) -> Option<&'a Checkpoint> {
+    match query {
+        "latest" => checkpoints.last(),
+        "best" => checkpoints
+            .iter()
+            .filter_map(|x| Some((x, x.meta.as_ref().ok()?.error_minutes)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(x, _)| x),
+        query => checkpoints.iter().rev().find(|x| x.time.starts_with(query)),
+    }
+}
+
+/// replaces the model at `model` with the checkpoint, the checkpoint has to
+/// be a model compatible with this build
+pub fn restore(model: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    Model::load(&checkpoint.path)?.save(model)
+}
+
+/// hash of the data points of the runs which doesn't depend on their order
+pub fn hash_runs<'a>(runs: impl IntoIterator<Item = &'a QueueRun>) -> u64 {
+    runs.into_iter().fold(0, |hash, run| {
+        let mut bytes = vec![];
+        for point in std::iter::once(&run.start).chain(&run.subsequent) {
+            bytes.extend(point.time.to_le_bytes());
+            bytes.extend(point.position.to_le_bytes());
+            bytes.extend(point.length.to_le_bytes());
+        }
+        hash.wrapping_add(fnv1a(0, &bytes))
+    })
+}
+
+fn stem(model: &Path) -> String {
+    model
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+/// time and suffix of the time in the name of a checkpoint, `None` if it
+/// isn't one
+fn sort_key(time: &str) -> Option<(NaiveDateTime, u32)> {
+    let (time, n) = match time.rsplit_once('-') {
+        Some((time, n)) if !n.contains(':') => (time, n.parse().ok()?),
+        _ => (time, 0),
+    };
+    let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(time, SECONDS_FORMAT))
+        .ok()?;
+    Some((time, n))
+}
+fn checkpoint_path(model: &Path, time: &str, extension: &str) -> PathBuf {
+    model.with_file_name(format!("{}.{time}.{extension}", stem(model)))
+}
@@ -0,0 +1,61 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use flate2::bufread::MultiGzDecoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+impl Compression {
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// opens a file, transparently decompressing it if it starts with the magic
+/// bytes of gzip or zstd or has a `.gz` or `.zst` extension
+pub fn open(p: impl AsRef<Path>) -> std::io::Result<Box<dyn Read>> {
+    let p = p.as_ref();
+    let mut f = BufReader::new(File::open(p)?);
+    let compression = Compression::from_magic(f.fill_buf()?).or_else(|| {
+        p.extension()
+            .and_then(|x| x.to_str())
+            .and_then(Compression::from_extension)
+    });
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(f)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(f)?),
+        None => Box::new(f),
+    })
+}
+
+/// extension of the file ignoring a trailing compression extension, for
+/// example `csv` for `run.csv.gz`
+pub fn data_extension(p: &Path) -> Option<&str> {
+    let ext = p.extension()?.to_str()?;
+    match Compression::from_extension(ext) {
+        Some(_) => Path::new(p.file_stem()?).extension()?.to_str(),
+        None => Some(ext),
+    }
+}
@@ -0,0 +1,133 @@
+/// removes the byte order mark which excel writes at the start of utf-8 csv
+/// files from the header
+pub(crate) fn strip_bom(header: &str) -> &str {
+    header.strip_prefix('\u{feff}').unwrap_or(header)
+}
+
+/// dialect of csv files
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// separator between fields, detected from the header if `None` by
+    /// picking the most common of `,`, `;` and tab
+    pub delimiter: Option<char>,
+    /// character used to quote fields containing the delimiter, a quote
+    /// inside a quoted field is written as two quotes
+    pub quote: Option<char>,
+    /// lines starting with this character are ignored
+    pub comment: Option<char>,
+    /// whether to remove whitespace around fields
+    pub trim: bool,
+}
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: None,
+            quote: Some('"'),
+            comment: Some('#'),
+            trim: true,
+        }
+    }
+}
+impl CsvOptions {
+    pub fn is_comment(&self, line: &str) -> bool {
+        self.comment
+            .is_some_and(|c| line.trim_start().starts_with(c))
+    }
+    /// fixes the delimiter to the one detected in `header` unless one is
+    /// already set
+    pub fn detect_delimiter(&self, header: &str) -> Self {
+        let delimiter = self.delimiter.unwrap_or_else(|| {
+            [',', ';', '\t']
+                .into_iter()
+                .rev()
+                .max_by_key(|d| header.matches(*d).count())
+                .unwrap()
+        });
+        CsvOptions {
+            delimiter: Some(delimiter),
+            ..self.clone()
+        }
+    }
+    /// splits a line into its fields, removing quotes
+    pub fn split(&self, line: &str) -> Vec<String> {
+        let delimiter = self.delimiter.unwrap_or(',');
+        let finish = |field: String| match self.trim {
+            true => field.trim().to_owned(),
+            false => field,
+        };
+
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                c if Some(c) == self.quote && quoted => {
+                    if chars.peek() == Some(&c) {
+                        field.push(c);
+                        chars.next();
+                    } else {
+                        quoted = false;
+                    }
+                }
+                // whitespace before an opening quote is dropped
+                c if Some(c) == self.quote && field.trim().is_empty() => {
+                    field.clear();
+                    quoted = true;
+                }
+                c if c == delimiter && !quoted => fields.push(finish(std::mem::take(&mut field))),
+                c => field.push(c),
+            }
+        }
+        fields.push(finish(field));
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        CsvOptions::default().detect_delimiter(line).split(line)
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(split(r#"a,"b ""c"" d",e"#), ["a", r#"b "c" d"#, "e"]);
+        assert_eq!(split(r#"a,  "b",c"#), ["a", "b", "c"]);
+        assert_eq!(split(r#""a,b";"c;d";e"#), ["a,b", "c;d", "e"]);
+        assert_eq!(split(r#""",x"#), ["", "x"]);
+    }
+
+    #[test]
+    fn untrimmed_fields() {
+        let csv = CsvOptions {
+            trim: false,
+            ..CsvOptions::default()
+        };
+        assert_eq!(csv.split(" a , b"), [" a ", " b"]);
+        assert_eq!(csv.split(r#" "a" ,b"#), ["a ", "b"]);
+    }
+
+    #[test]
+    fn delimiter_ties() {
+        let delimiter = |header| CsvOptions::default().detect_delimiter(header).delimiter;
+        assert_eq!(delimiter("time;position;length"), Some(';'));
+        assert_eq!(delimiter("time\tposition\tlength"), Some('\t'));
+        assert_eq!(delimiter("time,position;length"), Some(','));
+        assert_eq!(delimiter("time;position\tlength"), Some(';'));
+        assert_eq!(delimiter("time"), Some(','));
+        let csv = CsvOptions {
+            delimiter: Some('|'),
+            ..CsvOptions::default()
+        };
+        assert_eq!(csv.detect_delimiter("a,b,c").delimiter, Some('|'));
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        assert_eq!(strip_bom("\u{feff}time,position"), "time,position");
+        assert_eq!(strip_bom("time,position"), "time,position");
+    }
+}
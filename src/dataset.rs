@@ -0,0 +1,476 @@
+//! binary cache of parsed data directories so `train` and `stat` don't have to
+//! reparse every data file
+//!
+//! a cache is only used while it is fresh, that is it was built with the same
+//! load options from exactly the files which are found now, none of which were
+//! modified since, and its feature vectors were computed with the current
+//! version of their extractor
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    compress,
+    features::{self, FeatureExtractor},
+    loader_for_path,
+    nn::make_expected_result,
+    write_atomic, Error, LoadOptions, QueueDataPoint, QueueRun, QueueType, Result, RunMetadata,
+    WalkOptions,
+};
+
+const MAGIC: &[u8] = b"2b2qdata";
+/// version of the file format, caches of other versions are rejected
+pub const FORMAT_VERSION: u32 = 2;
+
+/// inputs and expected outputs of the network for every training point of a
+/// run
+pub type Features = Vec<(Vec<f64>, Vec<f64>)>;
+
+/// a data file together with its parsed run
+pub struct Source {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    /// the run or the reason it could not be loaded
+    pub run: std::result::Result<QueueRun, String>,
+    pub features: Option<Features>,
+}
+
+pub struct Dataset {
+    options: String,
+    /// name and version of the extractor the feature vectors were computed
+    /// with
+    features: Option<(String, u32)>,
+    sources: Vec<Source>,
+}
+impl Dataset {
+    /// loads every file selected by `walk` in parallel, files which can't be
+    /// loaded are kept as sources with an error, if `features` is set the
+    /// feature vectors of every run are computed with it as well, `cache` is
+    /// where the dataset will be written, which is skipped if it is in a root
+    pub fn build(
+        roots: &[impl AsRef<Path>],
+        walk: &WalkOptions,
+        options: &LoadOptions,
+        features: Option<&dyn FeatureExtractor>,
+        cache: Option<&Path>,
+    ) -> Result<Self> {
+        let sources = paths(roots, walk, cache)?
+            .into_par_iter()
+            .map(|path| {
+                let modified = modified(&path).map_err(|e| Error::from(e).in_file(&path))?;
+                let run = compress::open(&path)
+                    .map_err(Error::from)
+                    .and_then(|f| {
+                        loader_for_path(&path).unwrap_or(QueueRun::from_csv_file)(f, options)
+                    })
+                    .map_err(|e| e.to_string());
+                let features = match (&run, features) {
+                    (Ok(run), Some(features)) => Some(run_features(run, features)),
+                    _ => None,
+                };
+                Ok(Source {
+                    path,
+                    modified,
+                    run,
+                    features,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Dataset {
+            options: fingerprint(options),
+            features: features.map(|x| (x.name(), x.version())),
+            sources,
+        })
+    }
+    /// whether the cache at `cache` matches the files selected by `walk` and
+    /// `options` and its feature vectors were computed with the current
+    /// version of their extractor
+    pub fn is_fresh(
+        &self,
+        roots: &[impl AsRef<Path>],
+        walk: &WalkOptions,
+        options: &LoadOptions,
+        cache: &Path,
+    ) -> Result<bool> {
+        if self.options != fingerprint(options) {
+            return Ok(false);
+        }
+        if let Some((name, version)) = &self.features {
+            if features::by_name(name).map(|x| x.version()) != Some(*version) {
+                return Ok(false);
+            }
+        }
+        let paths = paths(roots, walk, Some(cache))?;
+        if paths.len() != self.sources.len() {
+            return Ok(false);
+        }
+        for (path, source) in paths.iter().zip(&self.sources) {
+            if *path != source.path || modified(path).ok() != Some(source.modified) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    /// extractor the feature vectors of the runs were computed with, `None`
+    /// if there are none or the extractor is unknown
+    pub fn extractor(&self) -> Option<Arc<dyn FeatureExtractor>> {
+        features::by_name(&self.features.as_ref()?.0)
+    }
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+    pub fn into_sources(self) -> Vec<Source> {
+        self.sources
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        File::open(path)
+            .map_err(Error::from)
+            .and_then(|f| Dataset::read_from(&mut BufReader::new(f)))
+            .map_err(|e| e.in_file(path))
+    }
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_atomic(path.as_ref(), |w| self.write_to(w))
+    }
+
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        r.read_exact(&mut magic).map_err(malformed)?;
+        if magic != MAGIC {
+            return Err(Error::Dataset("not a dataset cache".into()));
+        }
+        let version = read_u32(r)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Dataset(format!(
+                "unsupported format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        let features = match read_opt_str(r)? {
+            Some(name) => Some((name, read_u32(r)?)),
+            None => None,
+        };
+        let options = read_str(r)?;
+        let sources = (0..read_u64(r)?)
+            .map(|_| read_source(r))
+            .collect::<Result<_>>()?;
+        Ok(Dataset {
+            options,
+            features,
+            sources,
+        })
+    }
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        write_opt_str(w, self.features.as_ref().map(|x| &x.0[..]))?;
+        if let Some((_, version)) = &self.features {
+            w.write_all(&version.to_le_bytes())?;
+        }
+        write_str(w, &self.options)?;
+        w.write_all(&(self.sources.len() as u64).to_le_bytes())?;
+        for source in &self.sources {
+            write_source(w, source)?;
+        }
+        Ok(())
+    }
+}
+
+fn run_features(run: &QueueRun, features: &dyn FeatureExtractor) -> Features {
+    run.clone()
+        .into_iter()
+        .map(|point| (features.inputs(&point), make_expected_result(&point)))
+        .collect()
+}
+
+/// the files selected by `walk` except the cache and the temporary file it is
+/// written to, which would otherwise be loaded as data and make the cache stale
+/// whenever it is rewritten
+fn paths(
+    roots: &[impl AsRef<Path>],
+    walk: &WalkOptions,
+    cache: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let mut paths = walk.paths(roots)?;
+    if let Some(name) = cache.and_then(Path::file_name) {
+        let mut tmp = name.to_owned();
+        tmp.push(".tmp");
+        let dir = cache.and_then(canonical_dir);
+        paths.retain(|path| {
+            !matches!(path.file_name(), Some(x) if x == name || x == tmp)
+                || canonical_dir(path) != dir
+        });
+    }
+    Ok(paths)
+}
+fn canonical_dir(path: &Path) -> Option<PathBuf> {
+    match path.parent()? {
+        dir if dir.as_os_str().is_empty() => Path::new(".").canonicalize().ok(),
+        dir => dir.canonicalize().ok(),
+    }
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    std::fs::metadata(path)?.modified()
+}
+
+/// load options influence how files are parsed so a cache built with different
+/// ones is stale
+fn fingerprint(options: &LoadOptions) -> String {
+    format!("{options:?}")
+}
+
+fn malformed(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Dataset("unexpected end of file".into()),
+        _ => e.into(),
+    }
+}
+
+fn write_source(w: &mut impl Write, source: &Source) -> io::Result<()> {
+    write_str(w, &source.path.to_string_lossy())?;
+    let modified = source
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    w.write_all(&modified.as_secs().to_le_bytes())?;
+    w.write_all(&modified.subsec_nanos().to_le_bytes())?;
+    match &source.run {
+        Ok(run) => {
+            w.write_all(&[1])?;
+            write_run(w, run)?;
+        }
+        Err(message) => {
+            w.write_all(&[0])?;
+            write_str(w, message)?;
+        }
+    }
+    match &source.features {
+        Some(features) => {
+            w.write_all(&[1])?;
+            w.write_all(&(features.len() as u64).to_le_bytes())?;
+            for (inputs, expected) in features {
+                write_f64s(w, inputs)?;
+                write_f64s(w, expected)?;
+            }
+        }
+        None => w.write_all(&[0])?,
+    }
+    Ok(())
+}
+fn read_source(r: &mut impl Read) -> Result<Source> {
+    let path = PathBuf::from(read_str(r)?);
+    let (secs, nanos) = (read_u64(r)?, read_u32(r)?);
+    let modified = Some(nanos)
+        .filter(|&x| x < 1_000_000_000)
+        .and_then(|x| UNIX_EPOCH.checked_add(Duration::new(secs, x)))
+        .ok_or_else(|| Error::Dataset(format!("invalid modification time {secs}s {nanos}ns")))?;
+    let run = match read_u8(r)? {
+        0 => Err(read_str(r)?),
+        _ => Ok(read_run(r)?),
+    };
+    let features = match read_u8(r)? {
+        0 => None,
+        _ => Some(
+            (0..read_u64(r)?)
+                .map(|_| Ok((read_f64s(r)?, read_f64s(r)?)))
+                .collect::<Result<_>>()?,
+        ),
+    };
+    Ok(Source {
+        path,
+        modified,
+        run,
+        features,
+    })
+}
+
+fn write_run(w: &mut impl Write, run: &QueueRun) -> io::Result<()> {
+    let queue = match run.meta.queue {
+        None => 0,
+        Some(QueueType::Normal) => 1,
+        Some(QueueType::Priority) => 2,
+    };
+    w.write_all(&[queue])?;
+    write_opt_str(w, run.meta.server.as_deref())?;
+    write_opt_str(w, run.meta.client_version.as_deref())?;
+    w.write_all(&(run.subsequent.len() as u64).to_le_bytes())?;
+    for point in std::iter::once(&run.start).chain(&run.subsequent) {
+        w.write_all(&point.time.to_le_bytes())?;
+        w.write_all(&point.position.to_le_bytes())?;
+        w.write_all(&point.length.to_le_bytes())?;
+    }
+    Ok(())
+}
+fn read_run(r: &mut impl Read) -> Result<QueueRun> {
+    let queue = match read_u8(r)? {
+        0 => None,
+        1 => Some(QueueType::Normal),
+        _ => Some(QueueType::Priority),
+    };
+    let meta = RunMetadata {
+        queue,
+        server: read_opt_str(r)?,
+        client_version: read_opt_str(r)?,
+    };
+    let subsequent = read_u64(r)?;
+    let mut point = || -> Result<_> {
+        Ok(QueueDataPoint {
+            time: read_u64(r)?,
+            position: read_u16(r)?,
+            length: read_u16(r)?,
+        })
+    };
+    Ok(QueueRun {
+        start: point()?,
+        subsequent: (0..subsequent).map(|_| point()).collect::<Result<_>>()?,
+        meta,
+    })
+}
+
+fn write_str(w: &mut impl Write, s: &str) -> io::Result<()> {
+    w.write_all(&(s.len() as u32).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+/// reads at most as many bytes as are left in the file so a corrupt length
+/// can't allocate gigabytes
+fn read_str(r: &mut impl Read) -> Result<String> {
+    let len = read_u32(r)? as usize;
+    let mut buf = vec![];
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(Error::Dataset("unexpected end of file".into()));
+    }
+    String::from_utf8(buf).map_err(|e| Error::Dataset(e.to_string()))
+}
+fn write_opt_str(w: &mut impl Write, s: Option<&str>) -> io::Result<()> {
+    match s {
+        Some(s) => {
+            w.write_all(&[1])?;
+            write_str(w, s)
+        }
+        None => w.write_all(&[0]),
+    }
+}
+fn read_opt_str(r: &mut impl Read) -> Result<Option<String>> {
+    match read_u8(r)? {
+        0 => Ok(None),
+        _ => read_str(r).map(Some),
+    }
+}
+fn write_f64s(w: &mut impl Write, xs: &[f64]) -> io::Result<()> {
+    w.write_all(&(xs.len() as u32).to_le_bytes())?;
+    xs.iter().try_for_each(|x| w.write_all(&x.to_le_bytes()))
+}
+fn read_f64s(r: &mut impl Read) -> Result<Vec<f64>> {
+    (0..read_u32(r)?)
+        .map(|_| Ok(f64::from_le_bytes(read_bytes(r)?)))
+        .collect()
+}
+
+fn read_bytes<const N: usize>(r: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf).map_err(malformed)?;
+    Ok(buf)
+}
+fn read_u8(r: &mut impl Read) -> Result<u8> {
+    read_bytes(r).map(u8::from_le_bytes)
+}
+fn read_u16(r: &mut impl Read) -> Result<u16> {
+    read_bytes(r).map(u16::from_le_bytes)
+}
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    read_bytes(r).map(u32::from_le_bytes)
+}
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    read_bytes(r).map(u64::from_le_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset() -> Dataset {
+        let point = |time, position| QueueDataPoint {
+            time,
+            position,
+            length: 200,
+        };
+        let run = QueueRun {
+            start: point(1656000000000, 120),
+            subsequent: vec![point(1656000060000, 119), point(1656000120000, 117)],
+            meta: RunMetadata {
+                queue: Some(QueueType::Priority),
+                server: Some("connect.2b2t.org".into()),
+                client_version: None,
+            },
+        };
+        Dataset {
+            options: "options".into(),
+            features: Some(("v1".into(), 1)),
+            sources: vec![
+                Source {
+                    path: "data/a.csv".into(),
+                    modified: UNIX_EPOCH + Duration::new(1656000000, 123_456_789),
+                    run: Ok(run),
+                    features: Some(vec![(vec![0.5, -1.0], vec![0.25])]),
+                },
+                Source {
+                    path: "data/b.csv".into(),
+                    modified: UNIX_EPOCH,
+                    run: Err("empty run".into()),
+                    features: None,
+                },
+            ],
+        }
+    }
+
+    fn bytes(dataset: &Dataset) -> Vec<u8> {
+        let mut bytes = vec![];
+        dataset.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let written = bytes(&dataset());
+        let read = Dataset::read_from(&mut &written[..]).unwrap();
+        assert_eq!(read.options, "options");
+        assert_eq!(read.features, Some(("v1".into(), 1)));
+        let [a, b] = &read.sources[..] else {
+            panic!("expected two sources");
+        };
+        assert_eq!(a.path, Path::new("data/a.csv"));
+        assert_eq!(
+            a.modified,
+            UNIX_EPOCH + Duration::new(1656000000, 123_456_789)
+        );
+        let run = a.run.as_ref().unwrap();
+        assert_eq!(run.start.position, 120);
+        assert_eq!(run.subsequent.len(), 2);
+        assert_eq!(run.meta.server.as_deref(), Some("connect.2b2t.org"));
+        assert_eq!(a.features, Some(vec![(vec![0.5, -1.0], vec![0.25])]));
+        assert_eq!(b.run.as_ref().err().map(String::as_str), Some("empty run"));
+        assert_eq!(b.features, None);
+        assert_eq!(bytes(&read), written);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = bytes(&dataset());
+        for len in 0..bytes.len() {
+            match Dataset::read_from(&mut &bytes[..len]) {
+                Err(Error::Dataset(_)) => {}
+                Err(e) => panic!("truncated to {len} bytes: unexpected error {e}"),
+                Ok(_) => panic!("truncated to {len} bytes: read successfully"),
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{fmt, io, path::PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// reading from the file system failed
+    Io(io::Error),
+    /// no valid header was found, `line` is the first line which was tried
+    Header { line: usize, message: String },
+    /// a data row could not be parsed
+    Row { line: usize, message: String },
+    /// the run does not contain any data points
+    EmptyRun,
+    /// the run contains less data points than required
+    RunTooShort { points: usize, min_points: usize },
+    /// the model file does not contain a valid network
+    Model(String),
+    /// the model encodes its inputs differently than this build
+    IncompatibleModel(String),
+    /// the dataset cache is malformed or of an unsupported version
+    Dataset(String),
+    /// an include or exclude glob is invalid
+    Pattern(String),
+    /// the error occurred while loading the file at `path`
+    File { path: PathBuf, source: Box<Error> },
+}
+impl Error {
+    /// attaches the path of the file which caused the error
+    pub fn in_file(self, path: impl Into<PathBuf>) -> Self {
+        Error::File {
+            path: path.into(),
+            source: Box::new(self),
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Header { line, message } => write!(f, "line {line}: invalid header: {message}"),
+            Error::Row { line, message } => write!(f, "line {line}: invalid row: {message}"),
+            Error::EmptyRun => write!(f, "run contains no data points"),
+            Error::RunTooShort { points, min_points } => write!(
+                f,
+                "run contains {points} data points, at least {min_points} are required"
+            ),
+            Error::Model(message) => write!(f, "malformed model: {message}"),
+            Error::IncompatibleModel(message) => write!(f, "incompatible model: {message}"),
+            Error::Dataset(message) => write!(f, "malformed dataset cache: {message}"),
+            Error::Pattern(message) => write!(f, "invalid pattern: {message}"),
+            Error::File { path, source } => write!(f, "{path:?}: {source}"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::File { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
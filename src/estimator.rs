@@ -0,0 +1,120 @@
+//! the kinds of models which predict the remaining queue time
+//!
+//! a model file holds an `Estimator` mapping the inputs computed by its
+//! features to the outputs of its target, either a neural network, a ridge
+//! regression or an ensemble of boosted trees, `EtaModel` is what `stat`
+//! compares side by side, implemented by loaded models and by `old_eta`
+
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::{
+    boosted::Boosted, network::Network, old_eta, ridge::Ridge, Interval, TrainingDataPoint,
+};
+
+/// something predicting the remaining queue time of a data point
+pub trait EtaModel: Send + Sync {
+    /// predicted remaining time in hours, which may be negative
+    fn hours(&self, point: &TrainingDataPoint) -> f64;
+    /// interval around the prediction, `None` if the model has none
+    fn interval(&self, _point: &TrainingDataPoint) -> Option<Interval> {
+        None
+    }
+}
+
+/// the curve fitted by `old_eta`, which only looks at the current position
+/// and queue length
+pub struct OldEta;
+impl EtaModel for OldEta {
+    fn hours(&self, point: &TrainingDataPoint) -> f64 {
+        old_eta(point.current_position, point.current_length) / 3600.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelType {
+    Network,
+    Ridge,
+    Boosted,
+}
+impl FromStr for ModelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "network" => ModelType::Network,
+            "ridge" => ModelType::Ridge,
+            "boosted" => ModelType::Boosted,
+            _ => {
+                return Err(format!(
+                    "unknown model type {s:?}, expected network, ridge or boosted"
+                ))
+            }
+        })
+    }
+}
+impl ModelType {
+    /// name of the type, which is also the key of the estimator in the model
+    /// file
+    pub fn name(self) -> &'static str {
+        match self {
+            ModelType::Network => "network",
+            ModelType::Ridge => "ridge",
+            ModelType::Boosted => "boosted",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Estimator {
+    Network(Network),
+    Ridge(Ridge),
+    Boosted(Boosted),
+}
+impl Estimator {
+    /// an estimator which hasn't seen any data yet, `None` for networks
+    /// which are created with their layers by `Model::new`
+    pub fn untrained(model_type: ModelType, inputs: u32, outputs: u32) -> Option<Self> {
+        match model_type {
+            ModelType::Network => None,
+            ModelType::Ridge => Some(Estimator::Ridge(Ridge::new(inputs, outputs))),
+            ModelType::Boosted => Some(Estimator::Boosted(Boosted::default())),
+        }
+    }
+    pub fn model_type(&self) -> ModelType {
+        match self {
+            Estimator::Network(_) => ModelType::Network,
+            Estimator::Ridge(_) => ModelType::Ridge,
+            Estimator::Boosted(_) => ModelType::Boosted,
+        }
+    }
+    /// outputs for the inputs, encoded like the target of the model, the
+    /// outputs of estimators other than networks are clamped to the `0..1`
+    /// a network could produce
+    pub fn run(&self, inputs: &[f64]) -> Vec<f64> {
+        let outputs = match self {
+            Estimator::Network(net) => return net.run(inputs),
+            Estimator::Ridge(ridge) => ridge.run(inputs),
+            Estimator::Boosted(boosted) => boosted.run(inputs),
+        };
+        outputs
+            .into_iter()
+            .map(|x| x.clamp(f64::EPSILON, 1.0 - f64::EPSILON))
+            .collect()
+    }
+    pub fn to_json(&self) -> Value {
+        match self {
+            Estimator::Network(net) => net.to_json(),
+            Estimator::Ridge(ridge) => ridge.to_json(),
+            Estimator::Boosted(boosted) => boosted.to_json(),
+        }
+    }
+    pub fn from_json(model_type: ModelType, json: &Value) -> std::result::Result<Self, String> {
+        Ok(match model_type {
+            ModelType::Network => Estimator::Network(Network::from_json(json)?),
+            ModelType::Ridge => Estimator::Ridge(Ridge::from_json(json)?),
+            ModelType::Boosted => Estimator::Boosted(Boosted::from_json(json)?),
+        })
+    }
+}
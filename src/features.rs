@@ -0,0 +1,157 @@
+//! feature extractors encoding a data point into the inputs of a network
+//!
+//! every extractor has a name, which is recorded in the model files of the
+//! networks trained with it and selected with `--features`, and a version
+//! which has to be bumped whenever its inputs change
+
+use std::{f64::consts::TAU, sync::Arc};
+
+use chrono::{Datelike, Timelike};
+
+use crate::{
+    nn::{nn_position, nn_sigmoid_queue_time, sigmoid, time, POSITION_SCALE},
+    QueueDataPoint, TrainingDataPoint,
+};
+
+/// amount of samples before the snapshot over which the recent rate of the
+/// queue is measured, see `TrainingDataPoint::recent`
+pub const RECENT_SAMPLES: usize = 5;
+
+pub trait FeatureExtractor: Send + Sync {
+    /// name of the extractor which `by_name` parses, for example `v1`
+    fn name(&self) -> String;
+    /// bump it whenever `inputs` changes so cached feature vectors are
+    /// rebuilt and models trained on the old inputs are rejected
+    fn version(&self) -> u32;
+    /// encodes the data point, `expected_output` has to be ignored
+    fn inputs(&self, point: &TrainingDataPoint) -> Vec<f64>;
+    /// amount of values returned by `inputs`, which the first layer of a
+    /// network has to match
+    fn input_count(&self) -> u32 {
+        self.inputs(&TrainingDataPoint::default()).len() as u32
+    }
+}
+
+/// hour of day, day of week and minute of hour of the start and current time
+/// together with the start and current position and length, see
+/// `nn::make_inputs`
+pub struct V1;
+impl FeatureExtractor for V1 {
+    fn name(&self) -> String {
+        "v1".into()
+    }
+    fn version(&self) -> u32 {
+        1
+    }
+    fn inputs(&self, point: &TrainingDataPoint) -> Vec<f64> {
+        crate::nn::make_inputs(point)
+    }
+}
+
+/// time of day and day of week of the start and current time encoded as
+/// points on a circle, so 23:59 is next to 00:00, the start and current
+/// position and length, the time already spent in queue and the recent rate at
+/// which the position decreases
+///
+/// times are shifted by `utc_offset_minutes` before being encoded, named
+/// `v2` in utc and for example `v2+02:00` otherwise
+pub struct V2 {
+    pub utc_offset_minutes: i32,
+}
+impl V2 {
+    fn cyclical(&self, unix_millis: u64) -> [f64; 4] {
+        let time = time(unix_millis.saturating_add_signed(self.utc_offset_minutes as i64 * 60_000));
+        let day = (time.hour() as f64 + time.minute() as f64 / 60.0) / 24.0;
+        let week = (time.weekday().num_days_from_monday() as f64 + day) / 7.0;
+        [
+            (day * TAU).sin(),
+            (day * TAU).cos(),
+            (week * TAU).sin(),
+            (week * TAU).cos(),
+        ]
+    }
+}
+impl FeatureExtractor for V2 {
+    fn name(&self) -> String {
+        match self.utc_offset_minutes {
+            0 => "v2".into(),
+            x => format!(
+                "v2{}{:02}:{:02}",
+                if x < 0 { '-' } else { '+' },
+                x.abs() / 60,
+                x.abs() % 60
+            ),
+        }
+    }
+    fn version(&self) -> u32 {
+        1
+    }
+    fn inputs(&self, point: &TrainingDataPoint) -> Vec<f64> {
+        let elapsed = point.current_time.saturating_sub(point.start_time);
+        let mut inputs = vec![];
+        inputs.extend(self.cyclical(point.start_time));
+        inputs.extend(self.cyclical(point.current_time));
+        inputs.extend([
+            nn_position(point.start_position),
+            nn_position(point.start_length),
+            nn_position(point.current_position),
+            nn_position(point.current_length),
+            nn_sigmoid_queue_time(elapsed),
+            sigmoid(recent_rate(point) / POSITION_SCALE),
+        ]);
+        inputs
+    }
+}
+
+/// positions per hour the queue moved since `point.recent`, or since the
+/// start if it is unknown
+fn recent_rate(point: &TrainingDataPoint) -> f64 {
+    let recent = point.recent.unwrap_or(QueueDataPoint {
+        time: point.start_time,
+        position: point.start_position,
+        length: point.start_length,
+    });
+    let hours = point.current_time.saturating_sub(recent.time) as f64 / 1000.0 / 3600.0;
+    match hours > 0.0 {
+        true => (recent.position as f64 - point.current_position as f64) / hours,
+        false => 0.0,
+    }
+}
+
+/// extractor of models which don't record one
+pub fn default() -> Arc<dyn FeatureExtractor> {
+    Arc::new(V1)
+}
+
+/// looks up an extractor by the name it returns from `FeatureExtractor::name`
+pub fn by_name(name: &str) -> Option<Arc<dyn FeatureExtractor>> {
+    match name {
+        "v1" => Some(Arc::new(V1)),
+        "v2" => Some(Arc::new(V2 {
+            utc_offset_minutes: 0,
+        })),
+        name => {
+            let offset = name.strip_prefix("v2")?;
+            let (sign, offset) = match offset.split_at(1.min(offset.len())) {
+                ("+", offset) => (1, offset),
+                ("-", offset) => (-1, offset),
+                _ => return None,
+            };
+            let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+            let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+            if hours > 14 || minutes > 59 {
+                return None;
+            }
+            Some(Arc::new(V2 {
+                utc_offset_minutes: sign * (hours * 60 + minutes) as i32,
+            }))
+        }
+    }
+}
+
+/// looks up an extractor by name with an error listing the known ones
+pub fn parse(name: &str) -> std::result::Result<Arc<dyn FeatureExtractor>, String> {
+    by_name(name).ok_or_else(|| {
+        format!("unknown features {name:?}, expected v1, v2 or v2 with an offset such as v2+02:00")
+    })
+}
@@ -0,0 +1,177 @@
+//! uncertainty intervals around the predictions of a model
+//!
+//! `train` records how far the actual remaining time was from the prediction
+//! on the validation runs, or on the training runs if there are none, and
+//! stores the 10th, 50th and 90th percentile of these residuals for buckets
+//! of queue positions in the model file, a prediction is turned into an
+//! interval by adding them
+
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+/// upper bounds of the position buckets, positions above the last one fall
+/// into a final open bucket
+pub const BUCKETS: [u16; 6] = [25, 50, 100, 200, 400, 800];
+/// buckets with fewer residuals than this use the residuals of all positions
+pub const MIN_SAMPLES: u64 = 20;
+
+/// residual quantiles of the predictions of a model
+#[derive(Clone, Debug, PartialEq)]
+pub struct Intervals {
+    /// one bucket for every bound in `BUCKETS` and a final open one
+    pub buckets: Vec<Bucket>,
+    /// quantiles over all positions
+    pub all: Bucket,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    /// highest position in the bucket, `None` for the open bucket
+    pub max_position: Option<u16>,
+    /// amount of residuals the quantiles were computed from
+    pub samples: u64,
+    /// 10th, 50th and 90th percentile of the actual minus the predicted
+    /// remaining time in minutes
+    pub residual_minutes: [f64; 3],
+}
+
+/// a predicted remaining time together with the range the actual time fell
+/// into for 80% of the data points of the model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub p10: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+}
+impl Interval {
+    pub fn contains(&self, duration: Duration) -> bool {
+        (self.p10..=self.p90).contains(&duration)
+    }
+}
+
+impl Intervals {
+    /// computes the quantiles of residuals given as the current position
+    /// together with the actual minus the predicted remaining time in
+    /// minutes, `None` if there are none
+    pub fn from_residuals(residuals: impl IntoIterator<Item = (u16, f64)>) -> Option<Self> {
+        let mut buckets = vec![vec![]; BUCKETS.len() + 1];
+        let mut all = vec![];
+        for (position, residual) in residuals {
+            buckets[bucket_index(position)].push(residual);
+            all.push(residual);
+        }
+        Some(Intervals {
+            all: bucket(None, all)?,
+            buckets: buckets
+                .into_iter()
+                .enumerate()
+                .map(|(i, residuals)| {
+                    let max_position = BUCKETS.get(i).copied();
+                    bucket(max_position, residuals).unwrap_or(Bucket {
+                        max_position,
+                        samples: 0,
+                        residual_minutes: [0.0; 3],
+                    })
+                })
+                .collect(),
+        })
+    }
+    /// bucket of the position, or the quantiles over all positions if it has
+    /// too few residuals
+    pub fn bucket(&self, position: u16) -> &Bucket {
+        match self.buckets.get(bucket_index(position)) {
+            Some(bucket) if bucket.samples >= MIN_SAMPLES => bucket,
+            _ => &self.all,
+        }
+    }
+    /// interval around the remaining time `predicted` at the position,
+    /// clamped to zero
+    pub fn interval(&self, position: u16, predicted: Duration) -> Interval {
+        let [p10, p50, p90] = self.bucket(position).residual_minutes.map(|residual| {
+            Duration::try_from_secs_f64((predicted.as_secs_f64() + residual * 60.0).max(0.0))
+                .unwrap_or(Duration::MAX)
+        });
+        Interval { p10, p50, p90 }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert(
+            "buckets".into(),
+            Value::Array(self.buckets.iter().map(Bucket::to_json).collect()),
+        );
+        o.insert("all".into(), self.all.to_json());
+        Value::Object(o)
+    }
+    pub(crate) fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        let buckets = json
+            .get("buckets")
+            .and_then(Value::as_array)
+            .ok_or("invalid buckets")?
+            .iter()
+            .map(Bucket::from_json)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let expected: Vec<_> = BUCKETS.map(Some).into_iter().chain([None]).collect();
+        if buckets.iter().map(|x| x.max_position).ne(expected) {
+            return Err("intervals use different position buckets".into());
+        }
+        Ok(Intervals {
+            buckets,
+            all: Bucket::from_json(json.get("all").ok_or("missing key \"all\"")?)?,
+        })
+    }
+}
+
+impl Bucket {
+    fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("max_position".into(), json!(self.max_position));
+        o.insert("samples".into(), json!(self.samples));
+        for (key, residual) in ["p10", "p50", "p90"].iter().zip(self.residual_minutes) {
+            o.insert(key.to_string(), json!(residual));
+        }
+        Value::Object(o)
+    }
+    fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        let f64_field = |key| {
+            json.get(key)
+                .and_then(Value::as_f64)
+                .ok_or_else(|| format!("invalid {key}"))
+        };
+        Ok(Bucket {
+            max_position: match json.get("max_position") {
+                None | Some(Value::Null) => None,
+                Some(x) => Some(x.as_u64().ok_or("invalid max_position")? as u16),
+            },
+            samples: json
+                .get("samples")
+                .and_then(Value::as_u64)
+                .ok_or("invalid samples")?,
+            residual_minutes: [f64_field("p10")?, f64_field("p50")?, f64_field("p90")?],
+        })
+    }
+}
+
+fn bucket_index(position: u16) -> usize {
+    BUCKETS.partition_point(|&max| max < position)
+}
+
+fn bucket(max_position: Option<u16>, mut residuals: Vec<f64>) -> Option<Bucket> {
+    if residuals.is_empty() {
+        return None;
+    }
+    residuals.sort_by(f64::total_cmp);
+    Some(Bucket {
+        max_position,
+        samples: residuals.len() as u64,
+        residual_minutes: [0.1, 0.5, 0.9].map(|q| quantile(&residuals, q)),
+    })
+}
+
+/// quantile of sorted values, interpolating between the closest ones
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
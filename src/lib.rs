@@ -1,19 +1,177 @@
 use std::{
-    fs::{File, ReadDir},
-    io::{BufRead, BufReader, Lines},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Read, Write},
+    iter::Enumerate,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde_json::Value;
+
+pub mod boosted;
+pub mod check;
+pub mod checkpoint;
+pub mod compress;
+mod csv;
+pub mod dataset;
+mod error;
+pub mod estimator;
+pub mod features;
+pub mod interval;
+pub mod logs;
+pub mod model;
+pub mod network;
+pub mod ridge;
+pub mod schedule;
+mod split;
+pub mod sweep;
+pub mod target;
+mod time;
+mod walk;
+pub use csv::CsvOptions;
+pub use error::{Error, Result};
+pub use estimator::{Estimator, EtaModel, ModelType, OldEta};
+pub use features::FeatureExtractor;
+pub use interval::{Interval, Intervals};
+pub use model::{Model, ModelMeta};
+pub use network::Network;
+pub use split::Split;
+pub use target::Target;
+pub use time::TimeFormat;
+pub use walk::WalkOptions;
+
+/// options which apply to every loader
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    pub time_format: TimeFormat,
+    pub csv: CsvOptions,
+}
+
 #[derive(Clone)]
 pub struct QueueRun {
     pub start: QueueDataPoint,
     pub subsequent: Vec<QueueDataPoint>,
+    pub meta: RunMetadata,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueueType {
+    Normal,
+    Priority,
+}
+impl QueueType {
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueType::Normal => "normal",
+            QueueType::Priority => "priority",
+        }
+    }
+}
+impl FromStr for QueueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "normal" | "regular" => Ok(QueueType::Normal),
+            "priority" | "prio" => Ok(QueueType::Priority),
+            _ => Err(format!("unknown queue type {s:?}")),
+        }
+    }
+}
+
+/// information about a run which doesn't change between its data points
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct RunMetadata {
+    pub queue: Option<QueueType>,
+    /// name of the server or host the queue is on
+    pub server: Option<String>,
+    /// version of the client which recorded the run
+    pub client_version: Option<String>,
+}
+impl RunMetadata {
+    /// fills in the values of `other`, values which are present in both
+    /// have to be equal
+    fn merge(&mut self, other: RunMetadata) -> std::result::Result<(), String> {
+        fn merge<T: PartialEq + std::fmt::Debug>(
+            name: &str,
+            a: &mut Option<T>,
+            b: Option<T>,
+        ) -> std::result::Result<(), String> {
+            match (&a, b) {
+                (Some(a), Some(b)) if *a != b => Err(format!("{name} changed from {a:?} to {b:?}")),
+                (_, Some(b)) => {
+                    *a = Some(b);
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+        merge("queue", &mut self.queue, other.queue)?;
+        merge("server", &mut self.server, other.server)?;
+        merge("version", &mut self.client_version, other.client_version)
+    }
+}
+
+/// selects runs by their metadata, runs without a queue type are considered
+/// to be normal queue runs while runs without a server or client version
+/// never match a filter requiring one
+#[derive(Clone, Default, Debug)]
+pub struct RunFilter {
+    pub queue: Option<QueueType>,
+    pub server: Option<String>,
+    pub client_version: Option<String>,
+}
+impl RunFilter {
+    pub fn matches(&self, meta: &RunMetadata) -> bool {
+        fn matches<T: PartialEq>(filter: &Option<T>, value: &Option<T>) -> bool {
+            filter.is_none() || filter == value
+        }
+        matches(&self.queue, &Some(meta.queue.unwrap_or(QueueType::Normal)))
+            && matches(&self.server, &meta.server)
+            && matches(&self.client_version, &meta.client_version)
+    }
 }
 impl QueueRun {
+    /// last data point of the run, which is `start` if there are no
+    /// subsequent data points
+    pub fn end(&self) -> QueueDataPoint {
+        *self.subsequent.last().unwrap_or(&self.start)
+    }
+    /// amount of data points in the run including `start`
+    pub fn point_count(&self) -> usize {
+        self.subsequent.len() + 1
+    }
     pub fn start_training_point(&self) -> TrainingDataPoint {
-        self.start
-            .with_start_and_end(self.start, *self.subsequent.last().unwrap())
+        self.start.with_start_and_end(self.start, self.end())
+    }
+    /// writes the run as csv with a `time,position,length` header followed
+    /// by a column for every present metadata value
+    pub fn write_csv(&self, mut w: impl Write) -> Result<()> {
+        let meta = [
+            ("queue", self.meta.queue.map(QueueType::name)),
+            ("server", self.meta.server.as_deref()),
+            ("version", self.meta.client_version.as_deref()),
+        ];
+        let meta = meta.iter().filter_map(|(k, v)| Some((*k, (*v)?)));
+        let (mut meta_header, mut meta_values) = (String::new(), String::new());
+        for (k, v) in meta {
+            meta_header += &format!(",{k}");
+            meta_values += &format!(",{v}");
+        }
+
+        writeln!(w, "time,position,length{meta_header}")?;
+        for point in std::iter::once(&self.start).chain(&self.subsequent) {
+            writeln!(
+                w,
+                "{},{},{}{meta_values}",
+                point.time, point.position, point.length
+            )?;
+        }
+        w.flush()?;
+        Ok(())
     }
 }
 
@@ -23,6 +181,9 @@ enum CsvHeaderItem {
     Time,
     Position,
     Length,
+    Queue,
+    Server,
+    Version,
 }
 impl CsvHeaderItem {
     fn from_str(s: &str) -> Option<CsvHeaderItem> {
@@ -31,97 +192,226 @@ impl CsvHeaderItem {
             "time" => Some(Time),
             "position" => Some(Position),
             "length" | "currentqueuelength" | "current_queue_length" => Some(Length),
+            "queue" | "queuetype" | "queue_type" => Some(Queue),
+            "server" | "host" => Some(Server),
+            "version" | "clientversion" | "client_version" => Some(Version),
             _ => None,
         }
     }
-    fn vec_from_str<'a>(s: impl IntoIterator<Item = &'a str>) -> Option<Vec<CsvHeaderItem>> {
+    fn name(self) -> &'static str {
+        use CsvHeaderItem::*;
+        match self {
+            Time => "time",
+            Position => "position",
+            Length => "length",
+            Queue => "queue",
+            Server => "server",
+            Version => "version",
+        }
+    }
+    fn vec_from_str<'a>(
+        s: impl IntoIterator<Item = &'a str>,
+    ) -> std::result::Result<Vec<CsvHeaderItem>, String> {
         let mut v = Vec::new();
-        for mut s in s {
-            if let Some(stripped) = s.strip_suffix('\n') {
-                s = stripped;
-            };
-            let item = match CsvHeaderItem::from_str(s) {
-                Some(item) => item,
-                None => return None,
-            };
+        for s in s {
+            let item = CsvHeaderItem::from_str(s).ok_or_else(|| format!("unknown column {s:?}"))?;
             if v.contains(&item) {
-                return None;
+                return Err(format!("duplicate column {s:?}"));
             }
             v.push(item);
         }
-        Some(v)
+        Ok(v)
     }
 }
-fn parse_csv_header(s: String) -> Option<Vec<CsvHeaderItem>> {
-    CsvHeaderItem::vec_from_str(s.split(','))
+fn parse_csv_header(csv: &CsvOptions, s: &str) -> std::result::Result<Vec<CsvHeaderItem>, String> {
+    CsvHeaderItem::vec_from_str(csv.split(s).iter().map(String::as_str))
 }
-impl QueueRun {
-    fn from_csv_file(f: std::fs::File) -> Option<Self> {
-        let mut reader = std::io::BufReader::new(f);
-        let header = loop {
-            let mut header_s = String::new();
-            if reader.read_line(&mut header_s).is_err() || header_s.is_empty() {
-                return None;
-            };
-            if let Some(header) = parse_csv_header(header_s) {
-                break header;
+/// a data point together with the metadata found in the same row
+type Row = (QueueDataPoint, RunMetadata);
+fn set_row_item(
+    options: &LoadOptions,
+    i: CsvHeaderItem,
+    (
+        QueueDataPoint {
+            time,
+            position,
+            length,
+        },
+        meta,
+    ): &mut Row,
+    val: &str,
+) -> std::result::Result<(), String> {
+    let err = |e: std::num::ParseIntError| format!("invalid {} {val:?}: {e}", i.name());
+    match i {
+        // metadata is optional in every row
+        CsvHeaderItem::Queue | CsvHeaderItem::Server | CsvHeaderItem::Version if val.is_empty() => {
+            Ok(())
+        }
+        CsvHeaderItem::Time => match options.time_format.parse(val) {
+            Some(x) => {
+                *time = x;
+                Ok(())
             }
+            None => Err(format!("invalid time {val:?}")),
+        },
+        CsvHeaderItem::Position => FromStr::from_str(val).map(|x| *position = x).map_err(err),
+        CsvHeaderItem::Length => FromStr::from_str(val).map(|x| *length = x).map_err(err),
+        CsvHeaderItem::Queue => val.parse().map(|x| meta.queue = Some(x)),
+        CsvHeaderItem::Server => {
+            meta.server = Some(val.into());
+            Ok(())
+        }
+        CsvHeaderItem::Version => {
+            meta.client_version = Some(val.into());
+            Ok(())
+        }
+    }
+}
+/// parses a data row, columns without a header item are ignored
+fn parse_csv_row(
+    options: &LoadOptions,
+    header: &[Option<CsvHeaderItem>],
+    line: &str,
+) -> std::result::Result<Row, String> {
+    let values = options.csv.split(line);
+    if values.len() < header.len() {
+        return Err(format!(
+            "expected {} columns, found {}",
+            header.len(),
+            values.len()
+        ));
+    }
+    let mut y = Row::default();
+    for (val, i) in values.iter().zip(header.iter()) {
+        if let Some(i) = i {
+            set_row_item(options, *i, &mut y, val)?;
+        }
+    }
+    Ok(y)
+}
+/// parses a json object, keys are matched like csv header columns and
+/// unknown keys are ignored
+fn parse_jsonl_row(options: &LoadOptions, line: &str) -> std::result::Result<Row, String> {
+    let json: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let object = json.as_object().ok_or("expected a json object")?;
+    let mut y = Row::default();
+    let mut found = vec![];
+    for (key, val) in object {
+        let i = match CsvHeaderItem::from_str(key) {
+            Some(i) => i,
+            None => continue,
         };
-
-        fn set_queue_data_point_item(
-            i: CsvHeaderItem,
-            QueueDataPoint {
-                time,
-                position,
-                length,
-            }: &mut QueueDataPoint,
-            val: &str,
-        ) -> Option<()> {
-            match i {
-                CsvHeaderItem::Time => FromStr::from_str(val).map(|x| *time = x).ok(),
-                CsvHeaderItem::Position => FromStr::from_str(val).map(|x| *position = x).ok(),
-                CsvHeaderItem::Length => FromStr::from_str(val).map(|x| *length = x).ok(),
-            }
+        let val = match val {
+            Value::String(s) => s.clone(),
+            val => val.to_string(),
+        };
+        set_row_item(options, i, &mut y, &val)?;
+        found.push(i);
+    }
+    for i in [CsvHeaderItem::Time, CsvHeaderItem::Position] {
+        if !found.contains(&i) {
+            return Err(format!("missing key {:?}", i.name()));
         }
+    }
+    Ok(y)
+}
 
-        struct CsvQueueDataPointIterator {
-            lines: Lines<BufReader<File>>,
-            header: Vec<CsvHeaderItem>,
+/// parses every non-empty line into a data point, errors are annotated
+/// with their line number and lines for which `parse` returns `None` are
+/// skipped
+struct LineIterator<B, F> {
+    lines: Enumerate<Lines<B>>,
+    parse: F,
+    meta: RunMetadata,
+}
+impl<B: BufRead, F: FnMut(&str) -> std::result::Result<Option<Row>, String>> LineIterator<B, F> {
+    fn new(lines: Enumerate<Lines<B>>, parse: F) -> Self {
+        LineIterator {
+            lines,
+            parse,
+            meta: RunMetadata::default(),
         }
-        impl Iterator for CsvQueueDataPointIterator {
-            type Item = QueueDataPoint;
-
-            fn next(&mut self) -> Option<Self::Item> {
-                let mut y = QueueDataPoint::default();
-                while y == QueueDataPoint::default() {
-                    match self.lines.next() {
-                        Some(Ok(line)) => {
-                            for (val, i) in line.split(',').zip(self.header.iter()) {
-                                set_queue_data_point_item(*i, &mut y, val);
-                            }
-                        }
-                        Some(Err(_)) => {}
-                        None => return None,
-                    }
-                }
+    }
+    fn into_run(mut self) -> Result<QueueRun> {
+        let mut run = QueueRun::from_points(&mut self)?;
+        run.meta = self.meta;
+        Ok(run)
+    }
+}
+impl<B: BufRead, F: FnMut(&str) -> std::result::Result<Option<Row>, String>> Iterator
+    for LineIterator<B, F>
+{
+    type Item = Result<QueueDataPoint>;
 
-                Some(y)
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (n, line) = self.lines.next()?;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.trim().is_empty() {
+                continue;
             }
+            let row = match (self.parse)(&line) {
+                Ok(Some((point, meta))) => self.meta.merge(meta).map(|_| point),
+                Ok(None) => continue,
+                Err(message) => Err(message),
+            };
+            return Some(row.map_err(|message| Error::Row {
+                line: n + 1,
+                message,
+            }));
         }
+    }
+}
 
-        let mut iter = CsvQueueDataPointIterator {
-            lines: reader.lines(),
-            header,
+impl QueueRun {
+    fn from_points(mut iter: impl Iterator<Item = Result<QueueDataPoint>>) -> Result<Self> {
+        let start = iter.next().ok_or(Error::EmptyRun)??;
+        Ok(QueueRun {
+            start,
+            subsequent: iter.collect::<Result<_>>()?,
+            meta: RunMetadata::default(),
+        })
+    }
+    /// loads a run from a csv file with a header naming the columns
+    pub fn from_csv_file(f: impl Read, options: &LoadOptions) -> Result<Self> {
+        let mut lines = BufReader::new(f).lines().enumerate();
+        let mut header_err = None;
+        let (header, csv): (Vec<_>, _) = loop {
+            let (n, line) = match lines.next() {
+                Some((n, line)) => (n + 1, line?),
+                None => return Err(header_err.unwrap_or(Error::EmptyRun)),
+            };
+            let line = csv::strip_bom(&line);
+            if line.trim().is_empty() || options.csv.is_comment(line) {
+                continue;
+            }
+            let csv = options.csv.detect_delimiter(line);
+            match parse_csv_header(&csv, line) {
+                Ok(header) => break (header.into_iter().map(Some).collect(), csv),
+                Err(message) => {
+                    header_err.get_or_insert(Error::Header { line: n, message });
+                }
+            }
         };
-
-        let start = match iter.next() {
-            Some(x) => x,
-            None => return None,
+        let options = &LoadOptions {
+            csv,
+            ..options.clone()
         };
-        Some(QueueRun {
-            start,
-            subsequent: iter.collect(),
+
+        LineIterator::new(lines, |line: &str| match options.csv.is_comment(line) {
+            true => Ok(None),
+            false => parse_csv_row(options, &header, line).map(Some),
         })
+        .into_run()
+    }
+    /// loads a run from a file containing one json object per line, for
+    /// example `{"time":1656000000000,"position":412,"length":780}`
+    pub fn from_jsonl_file(f: impl Read, options: &LoadOptions) -> Result<Self> {
+        let lines = BufReader::new(f).lines().enumerate();
+        LineIterator::new(lines, |line: &str| parse_jsonl_row(options, line).map(Some)).into_run()
     }
 }
 impl IntoIterator for QueueRun {
@@ -136,7 +426,7 @@ impl IntoIterator for QueueRun {
         }
     }
 }
-#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct QueueDataPoint {
     pub time: u64,
     pub position: u16,
@@ -151,10 +441,12 @@ impl QueueDataPoint {
             current_time: self.time,
             current_position: self.position,
             current_length: self.length,
-            expected_output: end.time - self.time,
+            recent: None,
+            expected_output: end.time.saturating_sub(self.time),
         }
     }
 }
+#[derive(Default)]
 pub struct TrainingDataPoint {
     /// time at start in ms
     pub start_time: u64,
@@ -168,62 +460,133 @@ pub struct TrainingDataPoint {
     pub current_position: u16,
     /// queue length at snapshot
     pub current_length: u16,
+    /// data point `features::RECENT_SAMPLES` samples before the snapshot, or
+    /// the start if there are less, used to measure the recent rate of the
+    /// queue, `None` if unknown
+    pub recent: Option<QueueDataPoint>,
     /// time taken in ms
     pub expected_output: u64,
 }
 
+/// yields a training data point for every data point of the run, runs
+/// consisting of only `start` yield nothing as there is nothing to learn
 pub struct QueueRunIterator {
     inner: QueueRun,
-    count: u64,
+    count: usize,
 }
 impl Iterator for QueueRunIterator {
     type Item = TrainingDataPoint;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = match self.count {
-            x if x > self.inner.subsequent.len() as u64 => return None,
-            x if x == self.inner.subsequent.len() as u64 => self
-                .inner
-                .start
-                .with_start_and_end(self.inner.start, *self.inner.subsequent.last().unwrap()),
-            x => self.inner.subsequent[x as usize]
-                .with_start_and_end(self.inner.start, *self.inner.subsequent.last().unwrap()),
+        let end = *self.inner.subsequent.last()?;
+        let (point, recent) = match self.count {
+            x if x > self.inner.subsequent.len() => return None,
+            x if x == self.inner.subsequent.len() => (self.inner.start, None),
+            x => {
+                let recent = match (x + 1).checked_sub(features::RECENT_SAMPLES) {
+                    Some(0) | None => self.inner.start,
+                    Some(i) => self.inner.subsequent[i - 1],
+                };
+                (self.inner.subsequent[x], Some(recent))
+            }
         };
         self.count += 1;
-        Some(ret)
+        Some(TrainingDataPoint {
+            recent,
+            ..point.with_start_and_end(self.inner.start, end)
+        })
     }
 }
 
-pub fn load_file(
-    p: impl AsRef<Path>,
-    x: fn(std::fs::File) -> Option<QueueRun>,
-) -> Option<QueueRun> {
-    std::fs::File::open(p.as_ref()).ok().and_then(x)
+/// loads a run from a file, for example with `QueueRun::from_csv_file`
+pub type Loader = fn(Box<dyn Read>, &LoadOptions) -> Result<QueueRun>;
+
+/// loads a run from a file, gzip and zstd compressed files are decompressed
+/// before being passed to `x`
+pub fn load_file(p: impl AsRef<Path>, x: Loader, options: &LoadOptions) -> Result<QueueRun> {
+    compress::open(p.as_ref())
+        .map_err(Error::from)
+        .and_then(|f| x(f, options))
+        .map_err(|e| e.in_file(p.as_ref()))
 }
 
 pub struct QueueDataDir {
-    rd: ReadDir,
-    x: fn(std::fs::File) -> Option<QueueRun>,
+    paths: std::vec::IntoIter<PathBuf>,
+    x: Loader,
+    options: LoadOptions,
+    min_points: usize,
+}
+impl QueueDataDir {
+    pub fn options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+    /// rejects runs with less than `min_points` data points
+    pub fn min_points(mut self, min_points: usize) -> Self {
+        self.min_points = min_points;
+        self
+    }
 }
 impl Iterator for QueueDataDir {
-    type Item = Option<(QueueRun, PathBuf)>;
+    type Item = Result<(QueueRun, PathBuf)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.rd.next().map(|x| {
-            x.ok()
-                .and_then(|x| load_file(x.path(), self.x).map(|y| (y, x.path())))
+        self.paths.next().map(|path| {
+            let run = load_file(
+                &path,
+                loader_for_path(&path).unwrap_or(self.x),
+                &self.options,
+            )?;
+            if run.point_count() < self.min_points {
+                return Err(Error::RunTooShort {
+                    points: run.point_count(),
+                    min_points: self.min_points,
+                }
+                .in_file(path));
+            }
+            Ok((run, path))
         })
     }
 }
-pub fn load_dir(
-    p: impl AsRef<Path>,
-    x: fn(std::fs::File) -> Option<QueueRun>,
-) -> std::io::Result<QueueDataDir> {
-    std::fs::read_dir(p.as_ref()).map(|rd| QueueDataDir { rd, x })
+/// picks a loader based on the extension of the file, ignoring a compression
+/// extension such as the `.gz` of `run.csv.gz`
+pub fn loader_for_path(p: &Path) -> Option<Loader> {
+    match compress::data_extension(p)? {
+        "csv" => Some(QueueRun::from_csv_file),
+        "jsonl" | "ndjson" => Some(QueueRun::from_jsonl_file),
+        _ => None,
+    }
+}
+/// loads every file in a directory, files with a known extension are loaded
+/// with the matching loader and all other files with `x`
+pub fn load_dir(p: impl AsRef<Path>, x: Loader) -> Result<QueueDataDir> {
+    load_dirs(&[p], x, &WalkOptions::default())
+}
+/// loads the files of several directories selected by `walk`, in the order of
+/// `roots` and sorted by path within each root
+pub fn load_dirs(
+    roots: &[impl AsRef<Path>],
+    x: Loader,
+    walk: &WalkOptions,
+) -> Result<QueueDataDir> {
+    Ok(QueueDataDir {
+        paths: walk.paths(roots)?.into_iter(),
+        x,
+        options: LoadOptions::default(),
+        min_points: 1,
+    })
 }
-pub fn load_csv_dir(p: impl AsRef<Path>) -> std::io::Result<QueueDataDir> {
+pub fn load_csv_dir(p: impl AsRef<Path>) -> Result<QueueDataDir> {
     load_dir(p, QueueRun::from_csv_file)
 }
+pub fn load_csv_dir_with(p: impl AsRef<Path>, csv: CsvOptions) -> Result<QueueDataDir> {
+    load_csv_dir(p).map(|dir| {
+        dir.options(LoadOptions {
+            csv,
+            ..LoadOptions::default()
+        })
+    })
+}
 
 const C: f64 = 150.0;
 
@@ -272,28 +635,137 @@ fn linear(point: f64) -> f64 {
     a * point + b
 }
 
-pub fn load_model(path: impl AsRef<Path>) -> ::nn::NN {
-    let mut s = String::default();
-    std::io::Read::read_to_string(
-        &mut File::open(&path).expect("model path doesn't exist"),
-        &mut s,
-    )
-    .expect("failed reading model from file");
-    ::nn::NN::from_json(&s)
+/// loads the network of a model file, see `Model::load`, models of other
+/// types are rejected
+pub fn load_model(path: impl AsRef<Path>) -> Result<Network> {
+    let path = path.as_ref();
+    match Model::load(path)?.estimator {
+        Estimator::Network(net) => Ok(net),
+        x => Err(Error::IncompatibleModel(format!(
+            "model is a {} model, not a network",
+            x.model_type().name()
+        ))
+        .in_file(path)),
+    }
+}
+/// writes to a temporary file next to `path` which is renamed to `path` once
+/// it is complete, so an interrupted write keeps the previous file intact
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    File::create(&tmp)
+        .and_then(|f| {
+            let mut w = BufWriter::new(f);
+            write(&mut w)?;
+            w.into_inner()?.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            std::fs::remove_file(&tmp).ok();
+            Error::from(e).in_file(path)
+        })
+}
+
+/// wraps a network together with the encoding of its inputs and decoding of
+/// its outputs
+pub struct Predictor {
+    model: Model,
+    features: Arc<dyn FeatureExtractor>,
+}
+impl Predictor {
+    /// wraps a network of unknown origin, assuming it uses the default
+    /// features
+    pub fn new(net: Network) -> Self {
+        Predictor::with_features(net.into(), features::default())
+    }
+    /// wraps a model using the features recorded in it
+    pub fn from_model(model: Model) -> Result<Self> {
+        let features = model.meta.extractor().map_err(Error::IncompatibleModel)?;
+        Ok(Predictor::with_features(model, features))
+    }
+    /// wraps a model using `features`, which may be an extractor that isn't
+    /// part of this crate
+    pub fn with_features(model: Model, features: Arc<dyn FeatureExtractor>) -> Self {
+        Predictor { model, features }
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Model::load(path)
+            .and_then(|model| Predictor::from_model(model).map_err(|e| e.in_file(path)))
+    }
+    /// the network of the model, `None` for other model types
+    pub fn net(&self) -> Option<&Network> {
+        match &self.model.estimator {
+            Estimator::Network(net) => Some(net),
+            _ => None,
+        }
+    }
+    pub fn estimator(&self) -> &Estimator {
+        &self.model.estimator
+    }
+    pub fn meta(&self) -> &ModelMeta {
+        &self.model.meta
+    }
+    pub fn features(&self) -> &dyn FeatureExtractor {
+        &*self.features
+    }
+    /// the network of the model, `None` for other model types
+    pub fn into_net(self) -> Option<Network> {
+        match self.model.estimator {
+            Estimator::Network(net) => Some(net),
+            _ => None,
+        }
+    }
+    pub fn into_estimator(self) -> Estimator {
+        self.model.estimator
+    }
+    pub fn into_model(self) -> Model {
+        self.model
+    }
+    /// predicts the remaining time in queue, `expected_output` is ignored
+    pub fn predict(&self, point: &TrainingDataPoint) -> Duration {
+        let result = self.model.estimator.run(&self.features.inputs(point));
+        self.model.meta.target.to_duration(&result, point)
+    }
+    pub fn predict_batch(&self, points: &[TrainingDataPoint]) -> Vec<Duration> {
+        points.par_iter().map(|point| self.predict(point)).collect()
+    }
+    /// predicts the remaining time together with the interval recorded in
+    /// the model, `None` if the model was never trained
+    pub fn predict_interval(&self, point: &TrainingDataPoint) -> Option<Interval> {
+        let intervals = self.model.meta.intervals.as_ref()?;
+        Some(intervals.interval(point.current_position, self.predict(point)))
+    }
+}
+impl EtaModel for Predictor {
+    fn hours(&self, point: &TrainingDataPoint) -> f64 {
+        let result = self.model.estimator.run(&self.features.inputs(point));
+        self.model.meta.target.to_hours(&result, point)
+    }
+    fn interval(&self, point: &TrainingDataPoint) -> Option<Interval> {
+        self.predict_interval(point)
+    }
+}
+impl From<Network> for Predictor {
+    fn from(net: Network) -> Self {
+        Predictor::new(net)
+    }
 }
 
 pub struct LoggingDataPoint {
     file_path: PathBuf,
     pos: u16,
     len: u16,
-    inputs: Vec<f64>,
+    point: TrainingDataPoint,
     expected_time_h: f64,
-    old_pred_h: f64,
 }
 impl LoggingDataPoint {
     pub fn from_run(run: &QueueRun, file_path: PathBuf) -> Self {
         let training_point = run.start_training_point();
-        let inputs = nn::make_inputs(&training_point);
         let pos = run.start.position;
         let len = run.start.length;
         LoggingDataPoint {
@@ -301,36 +773,47 @@ impl LoggingDataPoint {
             pos,
             len,
             expected_time_h: training_point.expected_output as f64 / 1000.0 / 3600.0,
-            old_pred_h: old_eta(pos, len) / 3600.0,
-            inputs,
+            point: training_point,
         }
     }
 }
 pub mod nn {
+    use std::time::Duration;
+
     use chrono::{Datelike, NaiveDateTime, Timelike};
+    use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
-    use crate::{LoggingDataPoint, TrainingDataPoint};
+    use crate::{Estimator, EtaModel, LoggingDataPoint, Target, TrainingDataPoint};
 
-    pub fn log(nets: &[(&str, &nn::NN)], data_points: &[LoggingDataPoint]) {
-        let mut new: Vec<(&str, Vec<f64>)> = vec![];
-        let mut old = vec![];
+    /// prints the predictions of the models for every data point, with the
+    /// intervals of models which have them, followed by the mean absolute and
+    /// mean error of every model and how often the real time was inside its
+    /// interval
+    pub fn log(models: &[(&str, &dyn EtaModel)], data_points: &[LoggingDataPoint]) {
+        let mut errors = vec![(vec![], vec![]); models.len()];
         for (_n, point) in data_points.iter().enumerate() {
             println!("#{_n} {}/{} {:?}", point.pos, point.len, &point.file_path);
-            println!("pred\tdiff\tmodel");
-            let old_pred_h = point.old_pred_h;
-            let old_diff_minutes = (old_pred_h - point.expected_time_h) * 60.0;
-            old.push(old_diff_minutes);
-            for (n, (name, net)) in nets.iter().enumerate() {
-                let result = net.run(&point.inputs)[0];
-                let result_h = to_hours(result);
-                let new_diff_minutes = (result_h - point.expected_time_h) * 60.0;
-                new.get_mut(n)
-                    .map(|x| x.1.push(new_diff_minutes))
-                    .unwrap_or_else(|| new.push((name, vec![new_diff_minutes])));
-                println!("{result_h:.2}h\t{}m\t{name}", new_diff_minutes.floor());
+            println!("pred\tp10\tp50\tp90\tdiff\tmodel");
+            for ((name, model), (diffs, covered)) in models.iter().zip(&mut errors) {
+                let result_h = model.hours(&point.point);
+                let diff_minutes = (result_h - point.expected_time_h) * 60.0;
+                diffs.push(diff_minutes);
+                let quantiles = match model.interval(&point.point) {
+                    Some(interval) => {
+                        let expected = Duration::from_millis(point.point.expected_output);
+                        covered.push(interval.contains(expected));
+                        [interval.p10, interval.p50, interval.p90]
+                            .map(|x| format!("{:.2}h", x.as_secs_f64() / 3600.0))
+                            .join("\t")
+                    }
+                    None => "-\t-\t-".into(),
+                };
+                println!(
+                    "{result_h:.2}h\t{quantiles}\t{}m\t{name}",
+                    diff_minutes.floor()
+                );
             }
-            println!("{old_pred_h:.2}h\t{}m\told", old_diff_minutes.floor());
-            println!("{:.2}h\t   \treal\n", point.expected_time_h);
+            println!("{:.2}h\t\t\t\t   \treal\n", point.expected_time_h);
         }
         fn abs(slice: &[f64]) -> f64 {
             slice.iter().map(|x| x.abs()).sum::<f64>() / slice.len() as f64
@@ -338,34 +821,49 @@ pub mod nn {
         fn avg(slice: &[f64]) -> f64 {
             slice.iter().sum::<f64>() / slice.len() as f64
         }
-        println!("abs\tavg\tmodel");
-        for (name, new) in new {
-            println!("{:.1}m\t{:.1}m\t{name}", abs(&new), avg(&new));
+        fn coverage(covered: &[bool]) -> String {
+            match covered.len() {
+                0 => "-".into(),
+                len => format!(
+                    "{:.0}%",
+                    covered.iter().filter(|x| **x).count() as f64 * 100.0 / len as f64
+                ),
+            }
+        }
+        println!("abs\tavg\tp10-p90\tmodel");
+        for ((name, _), (diffs, covered)) in models.iter().zip(&errors) {
+            println!(
+                "{:.1}m\t{:.1}m\t{}\t{name}",
+                abs(diffs),
+                avg(diffs),
+                coverage(covered)
+            );
         }
-        println!("{:.1}m\t{:.1}m\told\n", abs(&old), avg(&old));
+        println!();
     }
 
     fn inv_sigmoid(b: f64) -> f64 {
         -((1.0 / b) - 1.0).ln()
     }
-    fn to_hours(b: f64) -> f64 {
-        inv_sigmoid(b) * 14.0
+    /// decodes the output of a network with the `sigmoid` target into the
+    /// remaining queue time in hours
+    pub fn to_hours(b: f64) -> f64 {
+        inv_sigmoid(b) * TIME_SCALE_HOURS
     }
-    fn nn_position(pos: u16) -> f64 {
-        sigmoid(pos as f64 / 512.0)
+    pub(crate) fn nn_position(pos: u16) -> f64 {
+        sigmoid(pos as f64 / POSITION_SCALE)
     }
     fn _rev_position(pos: f64) -> u16 {
-        (inv_sigmoid(pos) * 512.0) as u16
+        (inv_sigmoid(pos) * POSITION_SCALE) as u16
     }
-    fn sigmoid(a: f64) -> f64 {
+    pub(crate) fn sigmoid(a: f64) -> f64 {
         1.0 / (1.0 + (-a).exp())
     }
 
-    fn time(unix_millis: u64) -> NaiveDateTime {
-        chrono::prelude::NaiveDateTime::from_timestamp(
-            unix_millis as i64 / 1000,
-            (unix_millis % 1000) as u32 * 1000,
-        )
+    pub(crate) fn time(unix_millis: u64) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp_millis(unix_millis as i64)
+            .unwrap_or_default()
+            .naive_utc()
     }
     fn hour_of_day(time: NaiveDateTime) -> f64 {
         time.hour() as f64 / 23.0
@@ -376,9 +874,16 @@ pub mod nn {
     fn minute_of_hour(time: NaiveDateTime) -> f64 {
         time.minute() as f64 / 59.0
     }
-    fn nn_sigmoid_queue_time(time: u64) -> f64 {
-        sigmoid(time as f64 / 1000.0 / 3600.0 / 14.0)
+    pub(crate) fn nn_sigmoid_queue_time(time: u64) -> f64 {
+        sigmoid(time as f64 / 1000.0 / 3600.0 / TIME_SCALE_HOURS)
     }
+    /// queue positions and lengths are divided by this before the sigmoid
+    pub const POSITION_SCALE: f64 = 512.0;
+    /// queue times in hours are divided by this before the sigmoid
+    pub const TIME_SCALE_HOURS: f64 = 14.0;
+
+    /// inputs of the `v1` features, changing them requires bumping the
+    /// version of `features::V1`
     pub fn make_inputs(point: &TrainingDataPoint) -> Vec<f64> {
         let TrainingDataPoint {
             start_time,
@@ -406,8 +911,37 @@ pub mod nn {
             nn_position(*current_length),
         ]
     }
+    /// outputs of the default target, see `Target::encode`
     pub fn make_expected_result(point: &TrainingDataPoint) -> Vec<f64> {
-        vec![nn_sigmoid_queue_time(point.expected_output)]
+        Target::default().encode(point)
+    }
+    /// mean absolute difference in minutes between the predicted and the
+    /// actual remaining time of the examples, `points` are the data points
+    /// the examples were computed from
+    pub fn error_minutes(
+        estimator: &Estimator,
+        target: Target,
+        examples: &[(Vec<f64>, Vec<f64>)],
+        points: &[TrainingDataPoint],
+    ) -> f64 {
+        let residuals = residual_minutes(estimator, target, examples, points);
+        residuals.iter().map(|x| x.abs()).sum::<f64>() / residuals.len() as f64
+    }
+    /// actual minus predicted remaining time in minutes of every example
+    pub fn residual_minutes(
+        estimator: &Estimator,
+        target: Target,
+        examples: &[(Vec<f64>, Vec<f64>)],
+        points: &[TrainingDataPoint],
+    ) -> Vec<f64> {
+        examples
+            .par_iter()
+            .zip(points)
+            .map(|((inputs, _), point)| {
+                let expected_h = point.expected_output as f64 / 1000.0 / 3600.0;
+                (expected_h - target.to_hours(&estimator.run(inputs), point)) * 60.0
+            })
+            .collect()
     }
 }
 
@@ -0,0 +1,162 @@
+//! parsing of raw proxy logs such as the ones written by 2bored2wait into
+//! queue runs
+//!
+//! every line containing `Position in queue: N` and a timestamp becomes a data
+//! point, lines without either are ignored. the queue length is taken from a
+//! `/N` or `of N` suffix if present, otherwise the first position of the run
+//! is used
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{Error, QueueDataPoint, QueueRun, Result, TimeFormat};
+
+const MARKER: &str = "position in queue";
+
+/// parses a log into runs, a new run is started whenever the position
+/// increases, which happens on reconnects, or no position was logged for
+/// longer than `max_gap`
+pub fn parse_log(reader: impl BufRead, max_gap: Duration) -> Result<Vec<QueueRun>> {
+    let mut runs = vec![];
+    let mut current: Vec<QueueDataPoint> = vec![];
+    for line in reader.lines() {
+        let (time, position, length) = match parse_log_line(&line?) {
+            Some(x) => x,
+            None => continue,
+        };
+        if let Some(last) = current.last() {
+            if position > last.position
+                || time < last.time
+                || time - last.time > max_gap.as_millis() as u64
+            {
+                runs.extend(into_run(std::mem::take(&mut current)));
+            }
+        }
+        let length = length.unwrap_or_else(|| current.first().map_or(position, |x| x.length));
+        current.push(QueueDataPoint {
+            time,
+            position,
+            length,
+        });
+    }
+    runs.extend(into_run(current));
+    Ok(runs)
+}
+pub fn from_log_file(f: File, max_gap: Duration) -> Result<Vec<QueueRun>> {
+    parse_log(BufReader::new(f), max_gap)
+}
+
+fn into_run(points: Vec<QueueDataPoint>) -> Option<QueueRun> {
+    QueueRun::from_points(points.into_iter().map(Ok)).ok()
+}
+
+fn parse_log_line(line: &str) -> Option<(u64, u16, Option<u16>)> {
+    let index = line.to_ascii_lowercase().find(MARKER)?;
+    let prefix = &line[..index];
+    let rest = line[index + MARKER.len()..].trim_start_matches([':', ' ']);
+
+    let (position, rest) = split_number(rest)?;
+    let rest = rest.trim_start();
+    let length = rest
+        .strip_prefix('/')
+        .or_else(|| rest.strip_prefix("of "))
+        .and_then(|rest| split_number(rest.trim_start()))
+        .map(|(length, _)| length);
+
+    Some((parse_log_time(prefix)?, position, length))
+}
+
+fn split_number(s: &str) -> Option<(u16, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+/// tries the whole prefix, the first bracketed part and the first two words
+/// and then the first word of the prefix as timestamp, two words first so the
+/// time after a date isn't dropped when a level or name follows it
+fn parse_log_time(prefix: &str) -> Option<u64> {
+    let prefix = prefix.trim();
+    let bracketed = prefix
+        .strip_prefix('[')
+        .and_then(|x| x.split(']').next())
+        .unwrap_or(prefix);
+    let mut words = prefix.split_whitespace();
+    let first = words.next().unwrap_or_default();
+    let two = format!("{first} {}", words.next().unwrap_or_default());
+    let time = [prefix, bracketed, &two, first]
+        .into_iter()
+        .find_map(|s| TimeFormat::Auto.parse(s.trim_matches(|c: char| "[]():- ".contains(c))));
+    time
+}
+
+/// converts a log file into csv files in `out_dir` named after the log file,
+/// returns the amount of runs written
+pub fn convert_log_file(path: &Path, out_dir: &Path, max_gap: Duration) -> Result<usize> {
+    let runs = File::open(path)
+        .map_err(Error::from)
+        .and_then(|f| from_log_file(f, max_gap))
+        .map_err(|e| e.in_file(path))?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    for (n, run) in runs.iter().enumerate() {
+        let out = out_dir.join(format!("{stem}-{n}.csv"));
+        File::create(&out)
+            .map_err(Error::from)
+            .and_then(|f| run.write_csv(BufWriter::new(f)))
+            .map_err(|e| e.in_file(&out))?;
+    }
+    Ok(runs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2022-06-23 16:04:00 utc
+    const TIME: u64 = 1656000240000;
+
+    #[test]
+    fn bracketed_timestamp() {
+        assert_eq!(
+            parse_log_line("[2022-06-23 16:04:00] [Server thread/INFO]: Position in queue: 10"),
+            Some((TIME, 10, None))
+        );
+    }
+
+    #[test]
+    fn timestamp_followed_by_level() {
+        assert_eq!(
+            parse_log_line("2022-06-23 16:04:00 [INFO] Position in queue: 10"),
+            Some((TIME, 10, None))
+        );
+        assert_eq!(
+            parse_log_line("2022-06-23T16:04:00Z INFO Position in queue: 10"),
+            Some((TIME, 10, None))
+        );
+    }
+
+    #[test]
+    fn time_without_date_is_ignored() {
+        assert_eq!(parse_log_line("[16:04:00] Position in queue: 10"), None);
+        assert_eq!(parse_log_line("16:04:00 Position in queue: 10"), None);
+    }
+
+    #[test]
+    fn length_suffixes() {
+        assert_eq!(
+            parse_log_line("2022-06-23 16:04:00 Position in queue: 10/250"),
+            Some((TIME, 10, Some(250)))
+        );
+        assert_eq!(
+            parse_log_line("2022-06-23 16:04:00 Position in queue: 10 / 250"),
+            Some((TIME, 10, Some(250)))
+        );
+        assert_eq!(
+            parse_log_line("2022-06-23 16:04:00 position in queue: 10 of 250"),
+            Some((TIME, 10, Some(250)))
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! model files, an estimator wrapped in an envelope describing how its inputs
+//! are encoded and how it was trained
+//!
+//! ```json
+//! {"format":"2b2q-model","version":4,"created":1792189188992,"layers":[10,4,1],
+//!  "features":"v1","feature_version":1,"target":"sigmoid",
+//!  "position_scale":512.0,"time_scale_hours":14.0,"training":null,
+//!  "intervals":null,"type":"network",
+//!  "network":{"activation":"sigmoid","layers":[...],"num_inputs":10}}
+//! ```
+//!
+//! the estimator is stored under the name of its type, files written before
+//! the envelope existed only contain the network, they are loaded as if they
+//! were created with the first version of the `v1` features and the `sigmoid`
+//! target
+
+use std::{io::Write, path::Path, sync::Arc};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    estimator::{Estimator, ModelType},
+    features::{self, FeatureExtractor},
+    network::{Activation, Network},
+    nn::{POSITION_SCALE, TIME_SCALE_HOURS},
+    write_atomic, Error, Intervals, Result, Target,
+};
+
+const FORMAT: &str = "2b2q-model";
+/// version of the envelope, models of newer versions are rejected, version 1
+/// has no target and always uses `sigmoid`, versions before 3 always hold a
+/// network, versions before 4 only hold networks with sigmoid activations
+pub const FORMAT_VERSION: u64 = 4;
+/// features of models without an envelope or without a `features` key
+const LEGACY_FEATURES: (&str, u32) = ("v1", 1);
+
+/// an estimator together with its metadata
+#[derive(Clone, Debug)]
+pub struct Model {
+    pub estimator: Estimator,
+    pub meta: ModelMeta,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelMeta {
+    /// time the model was created by `new` in unix milliseconds, unknown for
+    /// models without an envelope
+    pub created: Option<u64>,
+    /// amount of nodes in every layer of the network, starting with the
+    /// inputs, only the amount of inputs and outputs for other estimators
+    pub layers: Vec<u32>,
+    /// name of the `FeatureExtractor` the inputs of the estimator are encoded
+    /// with
+    pub features: String,
+    /// version of the extractor at the time the model was created
+    pub feature_version: u32,
+    /// encoding of the remaining time in the outputs of the estimator
+    pub target: Target,
+    /// `nn::POSITION_SCALE` the inputs of the estimator are encoded with
+    pub position_scale: f64,
+    /// `nn::TIME_SCALE_HOURS` the times of the estimator are encoded with
+    pub time_scale_hours: f64,
+    /// how the model was last trained, `None` if it never was
+    pub training: Option<TrainingMeta>,
+    /// residuals of the predictions on the runs `training.error_minutes` was
+    /// measured on, `None` if the model was never trained
+    pub intervals: Option<Intervals>,
+}
+impl ModelMeta {
+    /// looks up the extractor of the model by its name
+    pub fn extractor(&self) -> std::result::Result<Arc<dyn FeatureExtractor>, String> {
+        features::parse(&self.features)
+    }
+    /// whether the estimator expects inputs encoded the way this build encodes
+    /// them and its first and last layer fit the inputs and outputs
+    pub fn check_compatible(&self) -> std::result::Result<(), String> {
+        self.check_compatible_with(&*self.extractor()?)
+    }
+    /// like `check_compatible` but with an extractor which may not be part of
+    /// this crate
+    pub fn check_compatible_with(
+        &self,
+        features: &dyn FeatureExtractor,
+    ) -> std::result::Result<(), String> {
+        if self.features != features.name() {
+            return Err(format!(
+                "model uses features {}, not {}",
+                self.features,
+                features.name()
+            ));
+        }
+        check_layers(features, self.target, &self.layers)?;
+        if self.feature_version != features.version() {
+            return Err(format!(
+                "model uses version {} of features {}, expected {}",
+                self.feature_version,
+                self.features,
+                features.version()
+            ));
+        }
+        if self.position_scale != POSITION_SCALE || self.time_scale_hours != TIME_SCALE_HOURS {
+            return Err(format!(
+                "model uses a position scale of {} and a time scale of {}h, expected \
+                 {POSITION_SCALE} and {TIME_SCALE_HOURS}h",
+                self.position_scale, self.time_scale_hours
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// whether a network with the given layers fits the inputs computed by
+/// `features` and the outputs encoded by `target`
+pub fn check_layers(
+    features: &dyn FeatureExtractor,
+    target: Target,
+    layers: &[u32],
+) -> std::result::Result<(), String> {
+    let (inputs, outputs) = (features.input_count(), target.output_count());
+    match layers {
+        [] | [_] => Err(format!(
+            "model has {} layers, at least an input and an output layer are required",
+            layers.len()
+        )),
+        [first, ..] if *first != inputs => Err(format!(
+            "model has {first} inputs, but {inputs} input values are computed"
+        )),
+        [.., last] if *last != outputs => Err(format!(
+            "model has {last} outputs, but {outputs} output values are expected"
+        )),
+        _ if layers.contains(&0) => Err("model has a layer without nodes".into()),
+        _ => Ok(()),
+    }
+}
+
+/// layers as written on the command line, such as `10-6-2-4-1`
+pub fn layers_to_string(layers: &[u32]) -> String {
+    let layers: Vec<_> = layers.iter().map(|x| x.to_string()).collect();
+    layers.join("-")
+}
+
+/// how a model was trained
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingMeta {
+    /// time the model was saved in unix milliseconds
+    pub created: u64,
+    /// training iterations since `train` was started
+    pub iterations: u32,
+    /// epochs trained since `train` was started, only known when training
+    /// for a fixed amount of epochs per iteration
+    pub epochs: Option<u64>,
+    /// optimiser the network was trained with, `None` for other estimators
+    /// and models trained before it was recorded
+    pub optimizer: Option<String>,
+    /// momentum of sgd, `None` for other optimisers and estimators
+    pub momentum: Option<f64>,
+    pub rate: f64,
+    /// hash of the training runs, see `checkpoint::hash_runs`
+    pub dataset: u64,
+    /// mean absolute error in minutes
+    pub error_minutes: f64,
+    /// whether `error_minutes` was measured on held-out runs rather than on
+    /// the training runs
+    pub validated: bool,
+}
+impl TrainingMeta {
+    pub(crate) fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("created".into(), json!(self.created));
+        o.insert("iterations".into(), json!(self.iterations));
+        o.insert("epochs".into(), json!(self.epochs));
+        o.insert("optimizer".into(), json!(self.optimizer));
+        o.insert("momentum".into(), json!(self.momentum));
+        o.insert("rate".into(), json!(self.rate));
+        o.insert("dataset".into(), json!(format!("{:016x}", self.dataset)));
+        o.insert("error_minutes".into(), json!(self.error_minutes));
+        o.insert("validated".into(), json!(self.validated));
+        Value::Object(o)
+    }
+    pub(crate) fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        Ok(TrainingMeta {
+            created: u64_field(json, "created")?,
+            iterations: u64_field(json, "iterations")? as u32,
+            epochs: match field(json, "epochs")? {
+                Value::Null => None,
+                _ => Some(u64_field(json, "epochs")?),
+            },
+            optimizer: match json.get("optimizer") {
+                None | Some(Value::Null) => None,
+                Some(x) => Some(x.as_str().ok_or("invalid optimizer")?.into()),
+            },
+            momentum: match field(json, "momentum")? {
+                Value::Null => None,
+                _ => Some(f64_field(json, "momentum")?),
+            },
+            rate: f64_field(json, "rate")?,
+            dataset: field(json, "dataset")?
+                .as_str()
+                .and_then(|s| u64::from_str_radix(s, 16).ok())
+                .ok_or("invalid dataset")?,
+            error_minutes: f64_field(json, "error_minutes")?,
+            validated: field(json, "validated")?
+                .as_bool()
+                .ok_or("invalid validated")?,
+        })
+    }
+}
+
+impl Model {
+    /// creates a randomly initialised network with the given layers and
+    /// activation of its hidden layers, encoding its inputs with `features`
+    /// and its outputs with `target`, panics with less than two layers or an
+    /// empty layer like `Network::new`
+    pub fn new(
+        layers: &[u32],
+        activation: Activation,
+        features: &dyn FeatureExtractor,
+        target: Target,
+    ) -> Self {
+        Model {
+            estimator: Estimator::Network(Network::new(layers, activation)),
+            meta: ModelMeta {
+                created: Some(chrono::Utc::now().timestamp_millis() as u64),
+                layers: layers.to_vec(),
+                features: features.name(),
+                feature_version: features.version(),
+                target,
+                position_scale: POSITION_SCALE,
+                time_scale_hours: TIME_SCALE_HOURS,
+                training: None,
+                intervals: None,
+            },
+        }
+    }
+    /// loads a model file, models whose inputs are encoded differently than
+    /// this build encodes them are rejected
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(|e| Error::from(e).in_file(path))?;
+        let model = Model::from_json(&s).map_err(|message| Error::Model(message).in_file(path))?;
+        model
+            .meta
+            .check_compatible()
+            .map_err(|message| Error::IncompatibleModel(message).in_file(path))?;
+        Ok(model)
+    }
+    /// writes the model to `path` without ever leaving a partially written
+    /// file behind
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_atomic(path.as_ref(), |w| write!(w, "{}", self.to_json()))
+    }
+    /// parses a model with or without an envelope
+    pub fn from_json(s: &str) -> std::result::Result<Self, String> {
+        if s.trim().is_empty() {
+            return Err("file is empty".into());
+        }
+        let json: Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        if json.get("num_inputs").is_some() {
+            let net = Network::from_json(&json)?;
+            return Ok(Model {
+                meta: ModelMeta {
+                    created: None,
+                    layers: net.layer_sizes(),
+                    features: LEGACY_FEATURES.0.into(),
+                    feature_version: LEGACY_FEATURES.1,
+                    target: Target::Sigmoid,
+                    position_scale: POSITION_SCALE,
+                    time_scale_hours: TIME_SCALE_HOURS,
+                    training: None,
+                    intervals: None,
+                },
+                estimator: Estimator::Network(net),
+            });
+        }
+        if json.get("format").and_then(Value::as_str) != Some(FORMAT) {
+            return Err("not a model file".into());
+        }
+        let version = u64_field(&json, "version")?;
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "unsupported format version {version}, expected {FORMAT_VERSION}"
+            ));
+        }
+        let model_type = match json.get("type") {
+            None => ModelType::Network,
+            Some(x) => x.as_str().ok_or("invalid type")?.parse()?,
+        };
+        let estimator = Estimator::from_json(model_type, field(&json, model_type.name())?)?;
+        let layers = match &estimator {
+            Estimator::Network(net) => net.layer_sizes(),
+            _ => field(&json, "layers")?
+                .as_array()
+                .and_then(|x| x.iter().map(|x| Some(x.as_u64()? as u32)).collect())
+                .ok_or("invalid layers")?,
+        };
+        if let Estimator::Ridge(ridge) = &estimator {
+            match layers[..] {
+                [inputs, outputs] => ridge.check_shape(inputs, outputs)?,
+                _ => return Err("ridge models have an input and an output layer".into()),
+            }
+        }
+        Ok(Model {
+            meta: ModelMeta {
+                created: json.get("created").and_then(Value::as_u64),
+                layers,
+                features: match json.get("features") {
+                    None => LEGACY_FEATURES.0.into(),
+                    Some(features) => features.as_str().ok_or("invalid features")?.into(),
+                },
+                feature_version: u64_field(&json, "feature_version")? as u32,
+                target: match json.get("target") {
+                    None => Target::Sigmoid,
+                    Some(target) => target.as_str().ok_or("invalid target")?.parse()?,
+                },
+                position_scale: f64_field(&json, "position_scale")?,
+                time_scale_hours: f64_field(&json, "time_scale_hours")?,
+                training: match json.get("training") {
+                    None | Some(Value::Null) => None,
+                    Some(training) => Some(TrainingMeta::from_json(training)?),
+                },
+                intervals: match json.get("intervals") {
+                    None | Some(Value::Null) => None,
+                    Some(intervals) => Some(Intervals::from_json(intervals)?),
+                },
+            },
+            estimator,
+        })
+    }
+    pub fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("format".into(), json!(FORMAT));
+        o.insert("version".into(), json!(FORMAT_VERSION));
+        o.insert("created".into(), json!(self.meta.created));
+        o.insert("layers".into(), json!(self.meta.layers));
+        o.insert("features".into(), json!(self.meta.features));
+        o.insert("feature_version".into(), json!(self.meta.feature_version));
+        o.insert("target".into(), json!(self.meta.target.name()));
+        o.insert("position_scale".into(), json!(self.meta.position_scale));
+        o.insert("time_scale_hours".into(), json!(self.meta.time_scale_hours));
+        o.insert(
+            "training".into(),
+            self.meta
+                .training
+                .as_ref()
+                .map_or(Value::Null, TrainingMeta::to_json),
+        );
+        o.insert(
+            "intervals".into(),
+            self.meta
+                .intervals
+                .as_ref()
+                .map_or(Value::Null, Intervals::to_json),
+        );
+        let model_type = self.estimator.model_type();
+        o.insert("type".into(), json!(model_type.name()));
+        o.insert(model_type.name().into(), self.estimator.to_json());
+        Value::Object(o)
+    }
+}
+impl From<Network> for Model {
+    /// wraps a network of unknown origin, assuming it encodes its inputs with
+    /// the current version of the default features and its outputs with the
+    /// default target
+    fn from(net: Network) -> Self {
+        let layers = net.layer_sizes();
+        Model {
+            estimator: Estimator::Network(net),
+            meta: ModelMeta {
+                created: None,
+                layers,
+                features: features::default().name(),
+                feature_version: features::default().version(),
+                target: Target::default(),
+                position_scale: POSITION_SCALE,
+                time_scale_hours: TIME_SCALE_HOURS,
+                training: None,
+                intervals: None,
+            },
+        }
+    }
+}
+
+fn field<'a>(json: &'a Value, key: &str) -> std::result::Result<&'a Value, String> {
+    json.get(key).ok_or_else(|| format!("missing key {key:?}"))
+}
+fn u64_field(json: &Value, key: &str) -> std::result::Result<u64, String> {
+    field(json, key)?
+        .as_u64()
+        .ok_or_else(|| format!("invalid {key}"))
+}
+fn f64_field(json: &Value, key: &str) -> std::result::Result<f64, String> {
+    field(json, key)?
+        .as_f64()
+        .ok_or_else(|| format!("invalid {key}"))
+}
@@ -0,0 +1,578 @@
+//! dense feed forward networks trained with mini-batches
+//!
+//! networks are stored in the json format of the `nn` crate this crate used
+//! to train with, every node has a bias followed by a weight for every node of
+//! the previous layer, so models written back then still load, the activation
+//! of the hidden layers is stored next to the layers and defaults to the
+//! sigmoid `nn` used, the output layer always uses a sigmoid so the outputs
+//! stay within the `0..1` the targets are encoded in
+
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde_json::{json, Map, Value};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Activation {
+    #[default]
+    Sigmoid,
+    Tanh,
+    Relu,
+}
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sigmoid" => Activation::Sigmoid,
+            "tanh" => Activation::Tanh,
+            "relu" => Activation::Relu,
+            _ => {
+                return Err(format!(
+                    "unknown activation {s:?}, expected sigmoid, tanh or relu"
+                ))
+            }
+        })
+    }
+}
+impl Activation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+        }
+    }
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => x.max(0.0),
+        }
+    }
+    /// derivative at the input which produced the output `y`
+    fn derivative(self, y: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => y * (1.0 - y),
+            Activation::Tanh => 1.0 - y * y,
+            Activation::Relu => (y > 0.0) as u8 as f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    pub num_inputs: u32,
+    /// nodes of every layer after the inputs, each a bias followed by a
+    /// weight for every node of the previous layer
+    pub layers: Vec<Vec<Vec<f64>>>,
+    /// activation of the hidden layers
+    pub activation: Activation,
+}
+
+/// how a network is trained
+#[derive(Clone, Copy, Debug)]
+pub struct TrainOptions {
+    pub optimizer: Optimizer,
+    pub rate: f64,
+    /// examples whose gradients are averaged into a single update
+    pub batch_size: usize,
+    /// every update shrinks the weights, but not the biases, by `rate` times
+    /// this
+    pub weight_decay: f64,
+    pub halt: Halt,
+    /// print the error every that many epochs
+    pub log_interval: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Optimizer {
+    /// gradient descent adding `momentum` times the previous update
+    Sgd {
+        momentum: f64,
+    },
+    Adam,
+}
+impl Optimizer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Optimizer::Sgd { .. } => "sgd",
+            Optimizer::Adam => "adam",
+        }
+    }
+    /// momentum of sgd, `None` for adam
+    pub fn momentum(self) -> Option<f64> {
+        match self {
+            Optimizer::Sgd { momentum } => Some(momentum),
+            Optimizer::Adam => None,
+        }
+    }
+    /// rate which works for most networks
+    pub fn default_rate(self) -> f64 {
+        match self {
+            Optimizer::Sgd { .. } => 0.3,
+            Optimizer::Adam => 0.001,
+        }
+    }
+}
+
+/// when `Trainer::train` returns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Halt {
+    Epochs(u32),
+    Timer(Duration),
+    /// the mean squared error of an epoch is at most this
+    Mse(f64),
+}
+
+/// trains a network, keeping the state of the optimiser between calls to
+/// `train`
+pub struct Trainer {
+    options: TrainOptions,
+    /// the previous update for sgd or the first moment for adam
+    first: Vec<Vec<Vec<f64>>>,
+    /// the second moment for adam
+    second: Vec<Vec<Vec<f64>>>,
+    steps: i32,
+    rng: Rng,
+}
+
+impl Network {
+    /// a network with the given amount of nodes in every layer, starting with
+    /// the inputs, and randomly initialised weights, panics with less than two
+    /// layers or an empty layer, which `model::check_layers` refuses
+    pub fn new(layers: &[u32], activation: Activation) -> Self {
+        assert!(layers.len() >= 2, "must have at least two layers");
+        assert!(!layers.contains(&0), "can't have any empty layers");
+        let mut rng = Rng::seeded();
+        let count = layers.len() - 1;
+        Network {
+            num_inputs: layers[0],
+            layers: layers
+                .windows(2)
+                .enumerate()
+                .map(|(i, sizes)| {
+                    let (inputs, outputs) = (sizes[0] as f64, sizes[1] as f64);
+                    let limit = match activation {
+                        Activation::Relu if i + 1 < count => (6.0 / inputs).sqrt(),
+                        _ => (6.0 / (inputs + outputs)).sqrt(),
+                    };
+                    (0..sizes[1])
+                        .map(|_| {
+                            std::iter::once(0.0)
+                                .chain((0..sizes[0]).map(|_| (rng.next_f64() * 2.0 - 1.0) * limit))
+                                .collect()
+                        })
+                        .collect()
+                })
+                .collect(),
+            activation,
+        }
+    }
+    /// amount of nodes in every layer, starting with the inputs
+    pub fn layer_sizes(&self) -> Vec<u32> {
+        std::iter::once(self.num_inputs)
+            .chain(self.layers.iter().map(|x| x.len() as u32))
+            .collect()
+    }
+    pub fn run(&self, inputs: &[f64]) -> Vec<f64> {
+        self.forward(inputs).pop().expect("at least the inputs")
+    }
+    /// outputs of every layer, starting with the inputs
+    fn forward(&self, inputs: &[f64]) -> Vec<Vec<f64>> {
+        let mut outputs = vec![inputs.to_vec()];
+        for (i, layer) in self.layers.iter().enumerate() {
+            let activation = match i + 1 == self.layers.len() {
+                true => Activation::Sigmoid,
+                false => self.activation,
+            };
+            let previous = outputs.last().expect("at least the inputs");
+            let layer = layer
+                .iter()
+                .map(|node| {
+                    let sum = node[0]
+                        + node[1..]
+                            .iter()
+                            .zip(previous)
+                            .map(|(w, x)| w * x)
+                            .sum::<f64>();
+                    activation.apply(sum)
+                })
+                .collect();
+            outputs.push(layer);
+        }
+        outputs
+    }
+    /// adds the gradient of half the squared error of the example to
+    /// `gradient` and returns the squared error
+    fn backpropagate(
+        &self,
+        (inputs, expected): &(Vec<f64>, Vec<f64>),
+        gradient: &mut [Vec<Vec<f64>>],
+    ) -> f64 {
+        let outputs = self.forward(inputs);
+        let result = outputs.last().expect("at least the inputs");
+        let error = result
+            .iter()
+            .zip(expected)
+            .map(|(y, t)| (y - t).powi(2))
+            .sum();
+        let mut delta: Vec<f64> = result
+            .iter()
+            .zip(expected)
+            .map(|(y, t)| (y - t) * Activation::Sigmoid.derivative(*y))
+            .collect();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let previous = &outputs[i];
+            for (node, d) in gradient[i].iter_mut().zip(&delta) {
+                node[0] += d;
+                for (g, x) in node[1..].iter_mut().zip(previous) {
+                    *g += d * x;
+                }
+            }
+            if i > 0 {
+                delta = previous
+                    .iter()
+                    .enumerate()
+                    .map(|(j, y)| {
+                        let sum: f64 = layer
+                            .iter()
+                            .zip(&delta)
+                            .map(|(node, d)| node[j + 1] * d)
+                            .sum();
+                        sum * self.activation.derivative(*y)
+                    })
+                    .collect();
+            }
+        }
+        error
+    }
+    fn zeros(&self) -> Vec<Vec<Vec<f64>>> {
+        self.layers
+            .iter()
+            .map(|layer| layer.iter().map(|node| vec![0.0; node.len()]).collect())
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("num_inputs".into(), json!(self.num_inputs));
+        o.insert("layers".into(), json!(self.layers));
+        o.insert("activation".into(), json!(self.activation.name()));
+        Value::Object(o)
+    }
+    /// parses a network written by `to_json` or by the `nn` crate
+    pub fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        let num_inputs = json
+            .get("num_inputs")
+            .and_then(Value::as_u64)
+            .ok_or("invalid num_inputs")? as u32;
+        let layers: Vec<Vec<Vec<f64>>> = json
+            .get("layers")
+            .and_then(Value::as_array)
+            .and_then(|layers| {
+                layers
+                    .iter()
+                    .map(|layer| {
+                        layer
+                            .as_array()?
+                            .iter()
+                            .map(|node| node.as_array()?.iter().map(Value::as_f64).collect())
+                            .collect()
+                    })
+                    .collect()
+            })
+            .ok_or("invalid layers")?;
+        let mut inputs = num_inputs as usize;
+        for layer in &layers {
+            if layer.is_empty() || layer.iter().any(|node| node.len() != inputs + 1) {
+                return Err("invalid layers".into());
+            }
+            inputs = layer.len();
+        }
+        if layers.is_empty() {
+            return Err("invalid layers".into());
+        }
+        Ok(Network {
+            num_inputs,
+            layers,
+            activation: match json.get("activation") {
+                None => Activation::Sigmoid,
+                Some(x) => x.as_str().ok_or("invalid activation")?.parse()?,
+            },
+        })
+    }
+}
+
+impl Trainer {
+    pub fn new(net: &Network, options: TrainOptions) -> Self {
+        Trainer {
+            options,
+            first: net.zeros(),
+            second: net.zeros(),
+            steps: 0,
+            rng: Rng::seeded(),
+        }
+    }
+    /// changes the rate of the following updates, keeping the state of the
+    /// optimiser
+    pub fn set_rate(&mut self, rate: f64) {
+        self.options.rate = rate;
+    }
+    /// trains on shuffled mini-batches of the examples until the halt
+    /// condition is met, returns the mean squared error of the last epoch
+    pub fn train(&mut self, net: &mut Network, examples: &[(Vec<f64>, Vec<f64>)]) -> f64 {
+        let start = Instant::now();
+        let mut order: Vec<usize> = (0..examples.len()).collect();
+        let mut epochs = 0;
+        loop {
+            for i in (1..order.len()).rev() {
+                order.swap(i, self.rng.below(i + 1));
+            }
+            let mut error = 0.0;
+            for batch in order.chunks(self.options.batch_size.max(1)) {
+                let (gradient, batch_error) = batch
+                    .par_iter()
+                    .fold(
+                        || (net.zeros(), 0.0),
+                        |(mut gradient, error), &i| {
+                            let e = net.backpropagate(&examples[i], &mut gradient);
+                            (gradient, error + e)
+                        },
+                    )
+                    .reduce(|| (net.zeros(), 0.0), add);
+                error += batch_error;
+                self.step(net, &gradient, batch.len());
+            }
+            epochs += 1;
+            let mse = error / examples.len().max(1) as f64;
+            if matches!(self.options.log_interval, Some(n) if n > 0 && epochs % n == 0) {
+                println!("epoch {epochs}: mean squared error {mse}");
+            }
+            let done = match self.options.halt {
+                Halt::Epochs(n) => epochs >= n,
+                Halt::Timer(duration) => start.elapsed() >= duration,
+                Halt::Mse(target) => mse <= target,
+            };
+            if done || examples.is_empty() {
+                return mse;
+            }
+        }
+    }
+    /// updates the weights with the summed gradient of `count` examples
+    fn step(&mut self, net: &mut Network, gradient: &[Vec<Vec<f64>>], count: usize) {
+        const BETA1: f64 = 0.9;
+        const BETA2: f64 = 0.999;
+        const EPSILON: f64 = 1e-8;
+        self.steps += 1;
+        let TrainOptions {
+            optimizer,
+            rate,
+            weight_decay,
+            ..
+        } = self.options;
+        let (correction1, correction2) =
+            (1.0 - BETA1.powi(self.steps), 1.0 - BETA2.powi(self.steps));
+        let nodes = net
+            .layers
+            .iter_mut()
+            .flatten()
+            .zip(gradient.iter().flatten())
+            .zip(
+                self.first
+                    .iter_mut()
+                    .flatten()
+                    .zip(self.second.iter_mut().flatten()),
+            );
+        for ((node, gradient), (first, second)) in nodes {
+            for (k, w) in node.iter_mut().enumerate() {
+                let g = gradient[k] / count as f64;
+                let update = match optimizer {
+                    Optimizer::Sgd { momentum } => {
+                        first[k] = momentum * first[k] + g;
+                        first[k]
+                    }
+                    Optimizer::Adam => {
+                        first[k] = BETA1 * first[k] + (1.0 - BETA1) * g;
+                        second[k] = BETA2 * second[k] + (1.0 - BETA2) * g * g;
+                        (first[k] / correction1) / ((second[k] / correction2).sqrt() + EPSILON)
+                    }
+                };
+                // the bias is the first value of a node
+                let decay = if k > 0 { weight_decay * *w } else { 0.0 };
+                *w -= rate * (update + decay);
+            }
+        }
+    }
+}
+
+fn add(
+    (mut a, e): (Vec<Vec<Vec<f64>>>, f64),
+    (b, f): (Vec<Vec<Vec<f64>>>, f64),
+) -> (Vec<Vec<Vec<f64>>>, f64) {
+    for (a, b) in a.iter_mut().flatten().zip(b.iter().flatten()) {
+        a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+    }
+    (a, e + f)
+}
+
+/// splitmix64, good enough to initialise weights and shuffle examples
+pub(crate) struct Rng(u64);
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+    fn seeded() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        // rngs seeded within the same clock tick still differ
+        static SEEDED: AtomicU64 = AtomicU64::new(0);
+        Rng(nanos as u64 ^ SEEDED.fetch_add(1, Ordering::Relaxed).rotate_left(32))
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// uniformly distributed in `0..1`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// uniformly distributed in `0..n`
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{features, Model, TrainingDataPoint};
+
+    /// half the squared error of the network for the example
+    fn loss(net: &Network, (inputs, expected): &(Vec<f64>, Vec<f64>)) -> f64 {
+        let result = net.run(inputs);
+        result
+            .iter()
+            .zip(expected)
+            .map(|(y, t)| (y - t).powi(2) / 2.0)
+            .sum()
+    }
+
+    #[test]
+    fn backpropagate_matches_finite_differences() {
+        let example = (vec![0.3, -0.7, 0.9], vec![0.2, 0.8]);
+        for activation in [Activation::Sigmoid, Activation::Tanh, Activation::Relu] {
+            let mut net = Network::new(&[3, 4, 3, 2], activation);
+            let mut gradient = net.zeros();
+            net.backpropagate(&example, &mut gradient);
+            for (l, layer) in gradient.iter().enumerate() {
+                for (n, node) in layer.iter().enumerate() {
+                    for (k, &g) in node.iter().enumerate() {
+                        const H: f64 = 1e-6;
+                        let w = net.layers[l][n][k];
+                        net.layers[l][n][k] = w + H;
+                        let above = loss(&net, &example);
+                        net.layers[l][n][k] = w - H;
+                        let below = loss(&net, &example);
+                        net.layers[l][n][k] = w;
+                        let numeric = (above - below) / (2.0 * H);
+                        assert!(
+                            (g - numeric).abs() < 1e-7,
+                            "{activation:?} layer {l} node {n} weight {k}: {g} != {numeric}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn single_node() -> Network {
+        Network {
+            num_inputs: 1,
+            layers: vec![vec![vec![0.0, 0.5]]],
+            activation: Activation::Sigmoid,
+        }
+    }
+    fn train_once(net: &mut Network, optimizer: Optimizer, weight_decay: f64, epochs: u32) {
+        let options = TrainOptions {
+            optimizer,
+            rate: 0.1,
+            batch_size: 1,
+            weight_decay,
+            halt: Halt::Epochs(epochs),
+            log_interval: None,
+        };
+        Trainer::new(net, options).train(net, &[(vec![1.0], vec![0.0])]);
+    }
+
+    #[test]
+    fn adam_step() {
+        // the first step of adam moves every weight by the rate against the
+        // sign of its gradient, the decay only shrinks the weight
+        let mut net = single_node();
+        train_once(&mut net, Optimizer::Adam, 0.5, 1);
+        let [bias, weight] = net.layers[0][0][..] else {
+            panic!("one bias and one weight")
+        };
+        assert!((bias - -0.1).abs() < 1e-6, "{bias}");
+        assert!(
+            (weight - (0.5 - 0.1 * (1.0 + 0.5 * 0.5))).abs() < 1e-6,
+            "{weight}"
+        );
+    }
+
+    #[test]
+    fn sgd_momentum_steps() {
+        let mut net = single_node();
+        train_once(&mut net, Optimizer::Sgd { momentum: 0.5 }, 0.0, 2);
+        // y = sigmoid(w x + b), the gradient of both weights is (y - 0) y (1 - y)
+        let gradient = |b: f64, w: f64| {
+            let y = 1.0 / (1.0 + (-(w + b)).exp());
+            y * y * (1.0 - y)
+        };
+        let g1 = gradient(0.0, 0.5);
+        let (b1, w1) = (-0.1 * g1, 0.5 - 0.1 * g1);
+        let g2 = gradient(b1, w1);
+        let update = 0.5 * g1 + g2;
+        let expected = [b1 - 0.1 * update, w1 - 0.1 * update];
+        for (actual, expected) in net.layers[0][0].iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-12, "{actual} != {expected}");
+        }
+    }
+
+    #[test]
+    fn legacy_model_matches_nn() {
+        // outputs of the model run by version 0.1.6 of the `nn` crate
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/models/10-8-2-4-1.json");
+        let model = Model::load(path).expect("legacy model loads");
+        let expected = [
+            (1656000000000, 200, 0.5514590578797455, 2.891947),
+            (1656261420000, 50, 0.5143777142334653, 0.805374),
+        ];
+        for (start_time, current_position, output, hours) in expected {
+            let point = TrainingDataPoint {
+                start_time,
+                start_position: 400,
+                start_length: 800,
+                current_time: start_time + 7_200_000,
+                current_position,
+                current_length: 800,
+                recent: None,
+                expected_output: 0,
+            };
+            let outputs = model.estimator.run(&features::default().inputs(&point));
+            assert!((outputs[0] - output).abs() < 1e-12, "{outputs:?}");
+            let predicted = model.meta.target.to_hours(&outputs, &point);
+            assert!((predicted - hours).abs() < 1e-6, "{predicted}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! linear regression with an l2 penalty on the weights, fitted in closed form
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde_json::{json, Map, Value};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ridge {
+    /// weights of every output, one for every input followed by the bias
+    pub weights: Vec<Vec<f64>>,
+}
+impl Ridge {
+    /// a regression predicting zero for every output
+    pub fn new(inputs: u32, outputs: u32) -> Self {
+        Ridge {
+            weights: vec![vec![0.0; inputs as usize + 1]; outputs as usize],
+        }
+    }
+    /// fits the weights minimising the squared error plus `lambda` times the
+    /// amount of examples times the squared weights, the bias isn't penalised,
+    /// without examples every output is zero
+    pub fn fit(examples: &[(Vec<f64>, Vec<f64>)], inputs: u32, outputs: u32, lambda: f64) -> Self {
+        if examples.is_empty() {
+            return Ridge::new(inputs, outputs);
+        }
+        let (inputs, outputs) = (inputs as usize, outputs as usize);
+        let n = inputs + 1;
+        // x^T x and x^T y of the inputs extended by a constant 1 for the bias
+        let zero = || (vec![0.0; n * n], vec![0.0; n * outputs]);
+        let (mut xtx, xty) = examples
+            .par_iter()
+            .fold(zero, |(mut xtx, mut xty), (x, y)| {
+                for i in 0..n {
+                    let xi = x.get(i).copied().unwrap_or(1.0);
+                    for j in 0..n {
+                        xtx[i * n + j] += xi * x.get(j).copied().unwrap_or(1.0);
+                    }
+                    for (k, yk) in y.iter().enumerate() {
+                        xty[k * n + i] += xi * yk;
+                    }
+                }
+                (xtx, xty)
+            })
+            .reduce(zero, |(mut a, mut b), (c, d)| {
+                a.iter_mut().zip(c).for_each(|(a, c)| *a += c);
+                b.iter_mut().zip(d).for_each(|(b, d)| *b += d);
+                (a, b)
+            });
+        for i in 0..inputs {
+            xtx[i * n + i] += lambda * examples.len() as f64;
+        }
+        Ridge {
+            weights: xty
+                .chunks(n)
+                .map(|b| solve(xtx.clone(), b.to_vec(), n))
+                .collect(),
+        }
+    }
+    pub fn run(&self, inputs: &[f64]) -> Vec<f64> {
+        self.weights
+            .iter()
+            .map(|w| {
+                let (bias, w) = w.split_last().expect("weights include the bias");
+                w.iter().zip(inputs).map(|(w, x)| w * x).sum::<f64>() + bias
+            })
+            .collect()
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let mut o = Map::new();
+        o.insert("weights".into(), json!(self.weights));
+        Value::Object(o)
+    }
+    pub(crate) fn from_json(json: &Value) -> std::result::Result<Self, String> {
+        let weights = json
+            .get("weights")
+            .and_then(Value::as_array)
+            .and_then(|outputs| {
+                outputs
+                    .iter()
+                    .map(|w| w.as_array()?.iter().map(Value::as_f64).collect())
+                    .collect::<Option<Vec<Vec<_>>>>()
+            })
+            .ok_or("invalid weights")?;
+        if weights.is_empty() || weights.iter().any(|w| w.is_empty()) {
+            return Err("invalid weights".into());
+        }
+        Ok(Ridge { weights })
+    }
+    /// checks that there are weights for every input and output
+    pub(crate) fn check_shape(&self, inputs: u32, outputs: u32) -> std::result::Result<(), String> {
+        if self.weights.len() != outputs as usize
+            || self.weights.iter().any(|w| w.len() != inputs as usize + 1)
+        {
+            return Err(format!(
+                "ridge weights don't match {inputs} inputs and {outputs} outputs"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// solves `a x = b` for the `n` by `n` matrix `a` by gaussian elimination,
+/// singular directions get a weight of zero
+fn solve(mut a: Vec<f64>, mut b: Vec<f64>, n: usize) -> Vec<f64> {
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs()))
+            .expect("rows left");
+        if a[pivot * n + col].abs() < 1e-12 {
+            continue;
+        }
+        for k in 0..n {
+            a.swap(col * n + k, pivot * n + k);
+        }
+        b.swap(col, pivot);
+        for row in 0..n {
+            if row != col {
+                let factor = a[row * n + col] / a[col * n + col];
+                for k in col..n {
+                    a[row * n + k] -= factor * a[col * n + k];
+                }
+                b[row] -= factor * b[col];
+            }
+        }
+    }
+    (0..n)
+        .map(|i| match a[i * n + i].abs() < 1e-12 {
+            true => 0.0,
+            false => b[i] / a[i * n + i],
+        })
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! learning rates changing across the iterations of `train`
+
+use std::{f64::consts::PI, str::FromStr};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Schedule {
+    /// keeps the rate the training started with
+    #[default]
+    Constant,
+    /// multiplies the rate by `factor` every `every` iterations
+    Step { every: u32, factor: f64 },
+    /// anneals the rate along a cosine to `min` times the initial rate over
+    /// `period` iterations and then restarts from the initial rate
+    Cosine { period: u32, min: f64 },
+    /// multiplies the rate by `factor` whenever `patience` iterations in a
+    /// row didn't improve the error
+    Plateau { patience: u32, factor: f64 },
+}
+impl FromStr for Schedule {
+    type Err = String;
+
+    /// parses `constant`, `step:N:FACTOR`, `cosine:N`, `cosine:N:MIN` or
+    /// `plateau:N:FACTOR`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let iterations = |s: &str| match s.parse::<u32>() {
+            Ok(x) if x > 0 => Ok(x),
+            _ => Err(format!(
+                "invalid amount of iterations {s:?}, expected a positive integer"
+            )),
+        };
+        let factor = |s: &str| match s.parse::<f64>() {
+            Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+            _ => Err(format!(
+                "invalid factor {s:?}, expected a number from 0 to 1"
+            )),
+        };
+        let (kind, rest) = s.split_once(':').unwrap_or((s, ""));
+        let (n, f) = rest.split_once(':').unwrap_or((rest, ""));
+        Ok(match kind {
+            "constant" if rest.is_empty() => Schedule::Constant,
+            "step" => Schedule::Step {
+                every: iterations(n)?,
+                factor: factor(f)?,
+            },
+            "cosine" => Schedule::Cosine {
+                period: iterations(n)?,
+                min: match f {
+                    "" => 0.0,
+                    f => factor(f)?,
+                },
+            },
+            "plateau" => Schedule::Plateau {
+                patience: iterations(n)?,
+                factor: factor(f)?,
+            },
+            _ => {
+                return Err(format!(
+                    "unknown schedule {s:?}, expected constant, step:, cosine: or plateau:"
+                ))
+            }
+        })
+    }
+}
+
+/// follows a schedule from an initial rate
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    schedule: Schedule,
+    initial: f64,
+    rate: f64,
+    iterations: u32,
+    stale: u32,
+}
+impl Scheduler {
+    pub fn new(schedule: Schedule, rate: f64) -> Self {
+        Scheduler {
+            schedule,
+            initial: rate,
+            rate,
+            iterations: 0,
+            stale: 0,
+        }
+    }
+    /// rate of the next iteration
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+    /// moves on to the next iteration after one which did or didn't improve
+    /// the error
+    pub fn advance(&mut self, improved: bool) {
+        self.iterations += 1;
+        self.stale = if improved { 0 } else { self.stale + 1 };
+        match self.schedule {
+            Schedule::Constant => {}
+            Schedule::Step { every, factor } => {
+                if self.iterations.is_multiple_of(every) {
+                    self.rate *= factor;
+                }
+            }
+            Schedule::Cosine { period, min } => {
+                let t = (self.iterations % period) as f64 / period as f64;
+                self.rate = self.initial * (min + (1.0 - min) * (1.0 + (PI * t).cos()) / 2.0);
+            }
+            Schedule::Plateau { patience, factor } => {
+                if self.stale >= patience {
+                    self.rate *= factor;
+                    self.stale = 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{path::Path, str::FromStr};
+
+use crate::{QueueRun, TimeFormat};
+
+/// divides runs into a training and a held-out validation set, whole runs are
+/// always kept together so no part of a validation run is trained on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Split {
+    /// holds out the given fraction of runs which started last
+    Ratio(f64),
+    /// holds out runs whose file name hashes below the given fraction, a run
+    /// stays on its side when other runs are added or removed
+    Hash { ratio: f64, seed: u64 },
+    /// holds out runs which started at or after the given unix milliseconds
+    Cutoff(u64),
+}
+impl Split {
+    /// returns the training and the validation items, `run` extracts the run
+    /// and its path from an item
+    pub fn apply<T>(
+        &self,
+        mut items: Vec<T>,
+        run: impl Fn(&T) -> (&QueueRun, &Path),
+    ) -> (Vec<T>, Vec<T>) {
+        match *self {
+            Split::Ratio(ratio) => {
+                items.sort_by_key(|x| run(x).0.start.time);
+                let held_out = (items.len() as f64 * ratio).round() as usize;
+                let validation = items.split_off(items.len() - held_out.min(items.len()));
+                (items, validation)
+            }
+            Split::Hash { ratio, seed } => items.into_iter().partition(|x| {
+                let name = run(x).1.file_name().unwrap_or_default();
+                let hash = fnv1a(seed, name.to_string_lossy().as_bytes());
+                (hash as f64 / u64::MAX as f64) >= ratio
+            }),
+            Split::Cutoff(cutoff) => items
+                .into_iter()
+                .partition(|x| run(x).0.start.time < cutoff),
+        }
+    }
+}
+impl FromStr for Split {
+    type Err = String;
+
+    /// parses `ratio:0.2`, `hash:0.2`, `hash:0.2:SEED` or `date:TIME`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ratio = |s: &str| match s.parse::<f64>() {
+            Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+            _ => Err(format!(
+                "invalid ratio {s:?}, expected a number from 0 to 1"
+            )),
+        };
+        let (kind, rest) = s.split_once(':').unwrap_or((s, ""));
+        Ok(match kind {
+            "ratio" => Split::Ratio(ratio(rest)?),
+            "hash" => {
+                let (r, seed) = rest.split_once(':').unwrap_or((rest, "0"));
+                let seed = seed.parse().map_err(|_| format!("invalid seed {seed:?}"))?;
+                Split::Hash {
+                    ratio: ratio(r)?,
+                    seed,
+                }
+            }
+            "date" => Split::Cutoff(
+                TimeFormat::Auto
+                    .parse(rest)
+                    .ok_or_else(|| format!("invalid time {rest:?}"))?,
+            ),
+            _ => {
+                return Err(format!(
+                    "unknown split {s:?}, expected ratio:, hash: or date:"
+                ))
+            }
+        })
+    }
+}
+
+/// fnv-1a followed by the murmur3 finalizer to spread short names over the
+/// whole range, unlike the hasher of std it is guaranteed to stay the same
+pub(crate) fn fnv1a(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325 ^ seed;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
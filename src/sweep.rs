@@ -0,0 +1,159 @@
+//! trains networks of different shapes and training parameters on the same
+//! runs to find out which of them predicts best
+
+use std::{io::Write, time::Instant};
+
+use crate::{
+    estimator::Estimator,
+    features::FeatureExtractor,
+    model,
+    network::{Activation, Halt, Optimizer, Rng, TrainOptions, Trainer},
+    nn::error_minutes,
+    schedule::{Schedule, Scheduler},
+    Model, Target, TrainingDataPoint,
+};
+
+/// a network shape together with how it is trained
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub layers: Vec<u32>,
+    pub optimizer: Optimizer,
+    /// rate the schedule starts from
+    pub rate: f64,
+}
+
+/// training parameters shared by every config of a sweep
+#[derive(Clone, Copy, Debug)]
+pub struct SweepOptions {
+    pub activation: Activation,
+    pub target: Target,
+    pub batch_size: usize,
+    pub weight_decay: f64,
+    /// when every iteration ends
+    pub halt: Halt,
+    pub iterations: u32,
+    pub schedule: Schedule,
+}
+
+/// result of training a config
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub config: Config,
+    /// lowest mean absolute error in minutes after any iteration
+    pub error_minutes: f64,
+    /// iteration reaching that error, starting at 1
+    pub iteration: u32,
+    pub seconds: f64,
+    /// the network after that iteration
+    pub model: Model,
+}
+
+/// every combination of the layers, optimisers and rates, the default rate of
+/// the optimiser is used if there are no rates
+pub fn grid(layers: &[Vec<u32>], optimizers: &[Optimizer], rates: &[f64]) -> Vec<Config> {
+    let mut configs = vec![];
+    for layers in layers {
+        for &optimizer in optimizers {
+            let defaults = [optimizer.default_rate()];
+            let rates = if rates.is_empty() { &defaults } else { rates };
+            for &rate in rates {
+                configs.push(Config {
+                    layers: layers.clone(),
+                    optimizer,
+                    rate,
+                });
+            }
+        }
+    }
+    configs
+}
+
+/// `n` configs picked at random, the same seed always picks the same ones
+pub fn sample(mut configs: Vec<Config>, n: usize, seed: u64) -> Vec<Config> {
+    let mut rng = Rng::new(seed);
+    let n = n.min(configs.len());
+    for i in 0..n {
+        let j = i + rng.below(configs.len() - i);
+        configs.swap(i, j);
+    }
+    configs.truncate(n);
+    configs
+}
+
+/// trains a new network for the config on the training examples and measures
+/// its error on the other examples after every iteration, `points` are the
+/// data points these were computed from
+pub fn run(
+    config: &Config,
+    options: &SweepOptions,
+    features: &dyn FeatureExtractor,
+    training: &[(Vec<f64>, Vec<f64>)],
+    examples: &[(Vec<f64>, Vec<f64>)],
+    points: &[TrainingDataPoint],
+) -> Outcome {
+    let start = Instant::now();
+    let mut model = Model::new(&config.layers, options.activation, features, options.target);
+    let mut best = (f64::INFINITY, 0, model.clone());
+    let mut scheduler = Scheduler::new(options.schedule, config.rate);
+    let train_options = TrainOptions {
+        optimizer: config.optimizer,
+        rate: config.rate,
+        batch_size: options.batch_size,
+        weight_decay: options.weight_decay,
+        halt: options.halt,
+        log_interval: None,
+    };
+    let mut trainer = None;
+    for iteration in 1..=options.iterations {
+        let Estimator::Network(net) = &mut model.estimator else {
+            break;
+        };
+        let trainer = trainer.get_or_insert_with(|| Trainer::new(net, train_options));
+        trainer.set_rate(scheduler.rate());
+        trainer.train(net, training);
+        let error = error_minutes(&model.estimator, options.target, examples, points);
+        let improved = error < best.0;
+        if improved {
+            best = (error, iteration, model.clone());
+        }
+        scheduler.advance(improved);
+    }
+    let (error_minutes, iteration, model) = best;
+    Outcome {
+        config: config.clone(),
+        error_minutes,
+        iteration,
+        seconds: start.elapsed().as_secs_f64(),
+        model,
+    }
+}
+
+/// sorts the outcomes from the lowest to the highest error
+pub fn rank(outcomes: &mut [Outcome]) {
+    outcomes.sort_by(|a, b| a.error_minutes.total_cmp(&b.error_minutes));
+}
+
+/// writes the outcomes as a tab separated table with a header
+pub fn write_table(outcomes: &[Outcome], mut w: impl Write) -> std::io::Result<()> {
+    writeln!(w, "rank\terror\titer\tsecs\tlayers\toptim\tmomentum\trate")?;
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let config = &outcome.config;
+        let momentum = config
+            .optimizer
+            .momentum()
+            .map_or("-".into(), |x| (x as f32).to_string());
+        writeln!(
+            w,
+            "{}\t{:.1}m\t{}\t{:.0}\t{}\t{}\t{}\t{}",
+            i + 1,
+            outcome.error_minutes,
+            outcome.iteration,
+            outcome.seconds,
+            model::layers_to_string(&config.layers),
+            config.optimizer.name(),
+            momentum,
+            config.rate as f32
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! encodings of the remaining queue time into the outputs of a network
+//!
+//! networks squash their outputs into `0..1`, so every target maps the
+//! remaining time into that range and back, the target of a model is recorded
+//! in its model file
+
+use std::{str::FromStr, time::Duration};
+
+use crate::{
+    nn::{nn_sigmoid_queue_time, to_hours, TIME_SCALE_HOURS},
+    TrainingDataPoint,
+};
+
+/// `log-seconds` outputs are the logarithm of the remaining seconds divided
+/// by this, which covers waits of up to 45h
+pub const LOG_SECONDS_SCALE: f64 = 12.0;
+/// `seconds-per-slot` outputs are the remaining seconds per position divided
+/// by this
+pub const SECONDS_PER_SLOT_SCALE: f64 = 300.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    /// sigmoid of the remaining hours divided by `nn::TIME_SCALE_HOURS`,
+    /// outputs saturate for long waits
+    #[default]
+    Sigmoid,
+    /// logarithm of the remaining seconds, which keeps long waits apart
+    LogSeconds,
+    /// remaining seconds divided by the current position, the rate of the
+    /// queue, which is similar for very different queue lengths
+    SecondsPerSlot,
+    /// remaining hours divided by `nn::TIME_SCALE_HOURS`, longer waits can't
+    /// be predicted
+    Hours,
+}
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sigmoid" => Target::Sigmoid,
+            "log-seconds" => Target::LogSeconds,
+            "seconds-per-slot" => Target::SecondsPerSlot,
+            "hours" => Target::Hours,
+            _ => {
+                return Err(format!(
+                    "unknown target {s:?}, expected sigmoid, log-seconds, seconds-per-slot or hours"
+                ))
+            }
+        })
+    }
+}
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Sigmoid => "sigmoid",
+            Target::LogSeconds => "log-seconds",
+            Target::SecondsPerSlot => "seconds-per-slot",
+            Target::Hours => "hours",
+        }
+    }
+    /// encodes the remaining time of the data point, its `expected_output`
+    pub fn encode(self, point: &TrainingDataPoint) -> Vec<f64> {
+        let seconds = point.expected_output as f64 / 1000.0;
+        vec![match self {
+            Target::Sigmoid => nn_sigmoid_queue_time(point.expected_output),
+            Target::LogSeconds => seconds.ln_1p() / LOG_SECONDS_SCALE,
+            Target::SecondsPerSlot => seconds / slots(point) / SECONDS_PER_SLOT_SCALE,
+            Target::Hours => seconds / 3600.0 / TIME_SCALE_HOURS,
+        }]
+    }
+    /// amount of values returned by `encode`, which the last layer of a
+    /// network has to match
+    pub fn output_count(self) -> u32 {
+        self.encode(&TrainingDataPoint::default()).len() as u32
+    }
+    /// decodes the outputs of a network for the data point into the remaining
+    /// time in hours
+    pub fn to_hours(self, outputs: &[f64], point: &TrainingDataPoint) -> f64 {
+        let output = outputs[0];
+        match self {
+            Target::Sigmoid => to_hours(output),
+            Target::LogSeconds => (output * LOG_SECONDS_SCALE).exp_m1() / 3600.0,
+            Target::SecondsPerSlot => output * SECONDS_PER_SLOT_SCALE * slots(point) / 3600.0,
+            Target::Hours => output * TIME_SCALE_HOURS,
+        }
+    }
+    /// decodes the outputs of a network into the remaining time, negative
+    /// predictions are clamped to zero
+    pub fn to_duration(self, outputs: &[f64], point: &TrainingDataPoint) -> Duration {
+        Duration::try_from_secs_f64((self.to_hours(outputs, point) * 3600.0).max(0.0))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+/// positions left to wait through, at least one so position 0 doesn't divide
+/// by zero
+fn slots(point: &TrainingDataPoint) -> f64 {
+    point.current_position.max(1) as f64
+}
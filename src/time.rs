@@ -0,0 +1,162 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+/// format of timestamps in data files, every format is normalised to unix
+/// milliseconds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TimeFormat {
+    /// detects unix seconds and milliseconds by their magnitude as well as
+    /// iso 8601 timestamps and dates, which are assumed to be utc if they lack
+    /// an offset
+    #[default]
+    Auto,
+    UnixMillis,
+    UnixSeconds,
+    Iso8601,
+    /// chrono format string, for example `%d.%m.%Y %H:%M:%S`, timestamps are
+    /// assumed to be utc
+    Custom(String),
+}
+impl TimeFormat {
+    /// unix milliseconds of the timestamp, `None` if it can't be parsed or is
+    /// before 1970
+    pub fn parse(&self, s: &str) -> Option<u64> {
+        let s = s.trim();
+        match self {
+            TimeFormat::Auto => match s.parse::<f64>() {
+                Ok(x) => unix_auto(x),
+                Err(_) => iso_8601(s),
+            },
+            TimeFormat::UnixMillis => s.parse().ok(),
+            TimeFormat::UnixSeconds => s.parse::<f64>().ok().and_then(|x| millis(x * 1000.0)),
+            TimeFormat::Iso8601 => iso_8601(s),
+            TimeFormat::Custom(format) => custom(s, format),
+        }
+    }
+}
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => TimeFormat::Auto,
+            "ms" | "millis" | "unix-millis" => TimeFormat::UnixMillis,
+            "s" | "seconds" | "unix-seconds" => TimeFormat::UnixSeconds,
+            "iso" | "iso8601" | "rfc3339" => TimeFormat::Iso8601,
+            s if s.contains('%') => TimeFormat::Custom(s.into()),
+            s => return Err(format!("unknown time format {s:?}")),
+        })
+    }
+}
+
+/// values below this are considered unix seconds, as milliseconds they would
+/// be in 1973 while as seconds they would be in the year 5138
+const SECONDS_LIMIT: f64 = 1e11;
+
+fn unix_auto(x: f64) -> Option<u64> {
+    if x < SECONDS_LIMIT {
+        millis(x * 1000.0)
+    } else {
+        millis(x)
+    }
+}
+
+/// rounds to whole milliseconds, `NaN`, infinite and negative values, which
+/// `as u64` would silently turn into `0` or `u64::MAX`, are rejected
+fn millis(x: f64) -> Option<u64> {
+    (0.0..u64::MAX as f64)
+        .contains(&x)
+        .then(|| x.round() as u64)
+}
+
+fn iso_8601(s: &str) -> Option<u64> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return u64::try_from(time.timestamp_millis()).ok();
+    }
+    [
+        "%Y-%m-%d %H:%M:%S%.f%#z",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+    ]
+    .into_iter()
+    .find_map(|format| custom(s, format))
+    .or_else(|| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .and_then(|time| u64::try_from(time.and_utc().timestamp_millis()).ok())
+    })
+}
+
+fn custom(s: &str, format: &str) -> Option<u64> {
+    if let Ok(time) = DateTime::parse_from_str(s, format) {
+        return u64::try_from(time.timestamp_millis()).ok();
+    }
+    NaiveDateTime::parse_from_str(s, format)
+        .ok()
+        .and_then(|time| u64::try_from(time.and_utc().timestamp_millis()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2022-06-23 16:04:00 utc
+    const TIME: u64 = 1656000240000;
+
+    #[test]
+    fn seconds_and_millis() {
+        let auto = |s| TimeFormat::Auto.parse(s);
+        assert_eq!(auto("1656000240"), Some(TIME));
+        assert_eq!(auto("1656000240.5"), Some(TIME + 500));
+        assert_eq!(auto("1656000240000"), Some(TIME));
+        assert_eq!(auto("99999999999"), Some(99_999_999_999_000));
+        assert_eq!(auto("100000000000"), Some(100_000_000_000));
+        assert_eq!(TimeFormat::UnixSeconds.parse("1656000240"), Some(TIME));
+        assert_eq!(TimeFormat::UnixMillis.parse("1656000240000"), Some(TIME));
+    }
+
+    #[test]
+    fn iso_8601() {
+        for s in [
+            "2022-06-23T16:04:00Z",
+            "2022-06-23T18:04:00+02:00",
+            "2022-06-23 18:04:00+02:00",
+            "2022-06-23T16:04:00",
+            "2022-06-23 16:04:00",
+            "2022-06-23 16:04:00.000",
+        ] {
+            assert_eq!(TimeFormat::Auto.parse(s), Some(TIME), "{s}");
+            assert_eq!(TimeFormat::Iso8601.parse(s), Some(TIME), "{s}");
+        }
+        assert_eq!(
+            TimeFormat::Auto.parse("2022-06-23"),
+            Some(TIME - 16 * 3_600_000 - 240_000)
+        );
+        let custom = TimeFormat::Custom("%d.%m.%Y %H:%M:%S".into());
+        assert_eq!(custom.parse("23.06.2022 16:04:00"), Some(TIME));
+    }
+
+    #[test]
+    fn rejected() {
+        for s in [
+            "NaN",
+            "inf",
+            "-inf",
+            "-1",
+            "-1656000240",
+            "1e400",
+            "",
+            "time",
+        ] {
+            assert_eq!(TimeFormat::Auto.parse(s), None, "{s}");
+            assert_eq!(TimeFormat::UnixSeconds.parse(s), None, "{s}");
+            assert_eq!(TimeFormat::UnixMillis.parse(s), None, "{s}");
+        }
+        assert_eq!(TimeFormat::Auto.parse("1969-12-31T23:59:59Z"), None);
+        assert_eq!(TimeFormat::Auto.parse("1969-12-31 23:59:59"), None);
+        let custom = TimeFormat::Custom("%d.%m.%Y %H:%M:%S".into());
+        assert_eq!(custom.parse("31.12.1969 23:59:59"), None);
+    }
+}
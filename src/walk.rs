@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{Error, Result};
+
+/// which files of a data directory are read
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// whether to descend into subdirectories
+    pub recursive: bool,
+    /// globs matched against the path relative to the data directory, for
+    /// example `2024-*/**.csv`, if any are given only matching files are read
+    pub include: Vec<String>,
+    /// globs matched like `include`, matching files are never read
+    pub exclude: Vec<String>,
+}
+impl WalkOptions {
+    /// lists the files in every root which pass the filters, the files of each
+    /// root are sorted by path so the order does not depend on the file system
+    pub fn paths(&self, roots: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+        let include = glob_set(&self.include)?;
+        let exclude = glob_set(&self.exclude)?;
+        let mut paths = vec![];
+        for root in roots {
+            let root = root.as_ref();
+            let mut files = vec![];
+            self.walk(root, &mut files)?;
+            files.retain(|path| {
+                let relative = path.strip_prefix(root).unwrap_or(path);
+                (self.include.is_empty() || include.is_match(relative))
+                    && !exclude.is_match(relative)
+            });
+            files.sort();
+            paths.extend(files);
+        }
+        Ok(paths)
+    }
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let entries = std::fs::read_dir(dir)
+            .and_then(|rd| {
+                rd.map(|x| x.map(|x| x.path()))
+                    .collect::<std::io::Result<Vec<_>>>()
+            })
+            .map_err(|e| Error::from(e).in_file(dir))?;
+        for path in entries {
+            if !path.is_dir() {
+                files.push(path);
+            } else if self.recursive {
+                self.walk(&path, files)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| Error::Pattern(e.to_string()))?);
+    }
+    builder.build().map_err(|e| Error::Pattern(e.to_string()))
+}